@@ -33,8 +33,8 @@ export function useConfirmRejectModal() {
   }
 
   return {
-    trustCertificate: (certificate: UntrustedCert) => {
-      return mountModal(TrustCertModal, { certificate })
+    trustCertificate: (certificate: UntrustedCert, chain: UntrustedCert[] = []) => {
+      return mountModal(TrustCertModal, { certificate, chain })
     },
   }
 }
@@ -18,6 +18,11 @@ export interface Connection {
   donotcache: boolean
   lastConnected: number | null
   showConsole: boolean
+  envVars: Record<string, string>
+  systemProperties: Record<string, string>
+  workingDir: string | null
+  // set for the read-only connections provided by a catalog
+  catalog?: string
 
   // the below properties are transient and are used only in the UI
   nodeId: string
@@ -31,3 +36,20 @@ export interface UntrustedCert {
   expires_on?: string,
   sha256sum: string,
 }
+
+export interface FieldError {
+  field: string
+  msg: string
+}
+
+// the fields locked by the policy the administrators of the machine installed
+export interface ManagedPolicy {
+  locked: string[]
+  allowedHosts: string[]
+  allowedJavaHomes: string[]
+}
+
+export interface ProfileList {
+  active: string
+  profiles: string[]
+}
@@ -0,0 +1,517 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+/// Arguments that cannot be checked, or that unlock the checked options in other ways, are
+/// always dropped regardless of the mode and the allow list.
+const BYPASS_RULES: &[(&str, &str)] = &[
+    ("@*", "argument files are expanded by the java launcher and cannot be checked"),
+    ("-xx:+unlockdiagnosticvmoptions", "unlocks VM options that are not covered by the policy"),
+    ("-xx:+unlockexperimentalvmoptions", "unlocks VM options that are not covered by the policy"),
+    ("-xx:flags=*", "VM options files cannot be checked"),
+    ("-xx:vmoptionsfile=*", "VM options files cannot be checked"),
+];
+
+/// Arguments that load code from outside the verified jars, or run commands, unless they are in the allow list.
+const DEFAULT_DENY: &[&str] = &[
+    "-javaagent:*",
+    "-agentpath:*",
+    "-agentlib:*",
+    "-xrun*",
+    "-xbootclasspath*",
+    "-xx:onoutofmemoryerror*",
+    "-xx:onerror*",
+    "-noverify",
+    "-xverify:none",
+    "-cp",
+    "-classpath",
+    "--class-path",
+    "--class-path=*",
+    "-jar",
+    "-p",
+    "--module-path",
+    "--module-path=*",
+    "--upgrade-module-path",
+    "--upgrade-module-path=*",
+    "--patch-module",
+    "--patch-module=*",
+    "-djava.class.path=*",
+    "-djava.system.class.loader=*",
+    "-djava.security.manager*",
+    "-djava.security.policy=*",
+    "-djava.security.properties=*",
+    "-djava.ext.dirs=*",
+    "-djava.endorsed.dirs=*",
+];
+
+/// Arguments a server has no business setting, unless they are in the allow list. A user may set them
+/// on a connection, e.g. to debug the administrator.
+const JNLP_DEFAULT_DENY: &[&str] = &[
+    // a log4j configuration can add appenders and lookups that run code or send the logs elsewhere
+    "-dlog4j.configurationfile=*",
+    "-dlog4j.configuration=*",
+    "-dlog4j2.*",
+    // a heap dump holds the passwords and session of the administrator, written wherever the path points
+    "-xx:+heapdumponoutofmemoryerror",
+    "-xx:heapdumppath=*",
+];
+
+/// The only arguments kept in the allowlist mode, besides those in the allow list of the policy file.
+const DEFAULT_ALLOW: &[&str] = &[
+    "-xmx*",
+    "-xms*",
+    "-xss*",
+    "-xx:maxmetaspacesize=*",
+    "-xx:+usegcoverheadlimit",
+    "-xx:+useg1gc",
+    "--add-modules",
+    "--add-modules=*",
+    "--add-opens",
+    "--add-opens=*",
+    "--add-exports",
+    "--add-exports=*",
+    "-dfile.encoding=*",
+    "-duser.language=*",
+    "-duser.country=*",
+    "-duser.timezone=*",
+    "-djava.awt.headless=*",
+    "-djava.net.preferipv4stack=*",
+    "-dsun.java2d.*",
+    "-dhttps.protocols=*",
+    "-djdk.tls.client.protocols=*",
+];
+
+/// Options whose value is the next argument, the value is kept or dropped along with the option.
+const TAKES_VALUE: &[&str] = &[
+    "-cp",
+    "-classpath",
+    "--class-path",
+    "-p",
+    "--module-path",
+    "--upgrade-module-path",
+    "--patch-module",
+    "--add-modules",
+    "--add-opens",
+    "--add-exports",
+    "--add-reads",
+    "--limit-modules",
+    "-jar",
+];
+
+/// The environment variables the JVM reads arguments from.
+pub const JAVA_OPTIONS_ENV_VARS: &[&str] = &["JDK_JAVA_OPTIONS", "JAVA_TOOL_OPTIONS", "_JAVA_OPTIONS"];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyMode {
+    /// everything except the denied arguments is kept
+    #[default]
+    Denylist,
+    /// only the allowed arguments are kept
+    Allowlist,
+}
+
+/// The policy for the JVM arguments coming from JNLP files and connections, read from ballista-jvm-policy.json.
+/// Patterns are matched case-insensitively against the whole argument, a trailing * matches any suffix.
+///
+/// An argument is dropped if it matches a bypass rule or the deny list, kept if it matches the allow list,
+/// dropped if it matches the default deny list, or the one of the JNLP arguments, and otherwise kept in the
+/// denylist mode or kept only if it matches the default allow list in the allowlist mode.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ArgPolicy {
+    #[serde(default)]
+    pub mode: PolicyMode,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgSource {
+    /// java-vm-args and <property> elements of the JNLP file
+    Jnlp,
+    /// the heap size, system properties, java args and environment variables of the connection
+    Connection,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DroppedArg {
+    pub arg: String,
+    pub source: ArgSource,
+    pub reason: String,
+}
+
+impl ArgPolicy {
+    /// The default policy is used when the file doesn't exist, an invalid file fails the launch.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(ArgPolicy::default());
+        }
+        let f = File::open(path)?;
+        serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| Error::msg(format!("invalid JVM argument policy {:?}: {}", path, e)))
+    }
+
+    /// The reason the argument is dropped, None if it is kept.
+    pub fn check(&self, arg: &str, source: ArgSource) -> Option<String> {
+        if let Some(reason) = check_bypass(arg) {
+            return Some(reason);
+        }
+        let arg = normalize(arg);
+        if let Some(p) = self.deny.iter().find(|p| matches(p, &arg)) {
+            return Some(format!("denied by the policy ({})", p));
+        }
+        if self.allow.iter().any(|p| matches(p, &arg)) {
+            return None;
+        }
+        if let Some(p) = DEFAULT_DENY.iter().find(|p| matches(p, &arg)) {
+            return Some(format!("denied by default ({})", p));
+        }
+        if source == ArgSource::Jnlp {
+            if let Some(p) = JNLP_DEFAULT_DENY.iter().find(|p| matches(p, &arg)) {
+                return Some(format!("denied by default for JNLP files ({})", p));
+            }
+        }
+        match self.mode {
+            PolicyMode::Denylist => None,
+            PolicyMode::Allowlist if DEFAULT_ALLOW.iter().any(|p| matches(p, &arg)) => None,
+            PolicyMode::Allowlist => Some(String::from("not in the allow list")),
+        }
+    }
+
+    /// Filters the arguments, the values of the options in TAKES_VALUE are kept or dropped with them.
+    pub fn filter<S: AsRef<str>>(&self, args: &[S], source: ArgSource, dropped: &mut Vec<DroppedArg>) -> Vec<String> {
+        let mut kept = Vec::with_capacity(args.len());
+        let mut itr = args.iter().map(AsRef::as_ref);
+        while let Some(arg) = itr.next() {
+            let value = if TAKES_VALUE.contains(&normalize(arg).as_str()) {
+                itr.next()
+            } else {
+                None
+            };
+            // the launcher expands argument files in the values too
+            let reason = self.check(arg, source).or_else(|| value.and_then(check_bypass));
+            match reason {
+                None => {
+                    kept.push(arg.to_string());
+                    kept.extend(value.map(String::from));
+                }
+                Some(reason) => {
+                    let arg = match value {
+                        Some(v) => format!("{} {}", arg, v),
+                        None => arg.to_string(),
+                    };
+                    println!("dropping JVM argument {}: {}", arg, reason);
+                    dropped.push(DroppedArg { arg, source, reason });
+                }
+            }
+        }
+        kept
+    }
+
+    /// Removes the allow entries that would let an argument of the default deny list through, the policies of
+    /// the profiles can be edited by the user and must not override it while a managed policy is installed.
+    pub fn without_default_deny_overrides(mut self) -> Self {
+        self.allow.retain(|p| {
+            let p = normalize(p);
+            let overrides = DEFAULT_DENY.iter().any(|d| overlaps(&p, d));
+            if overrides {
+                println!("ignoring the allowed JVM argument {} of the profile's policy, it is denied by default", p);
+            }
+            !overrides
+        });
+        self
+    }
+
+    /// Filters the arguments of an options string, e.g. java-vm-args or the value of JDK_JAVA_OPTIONS.
+    pub fn filter_options(&self, options: &str, source: ArgSource, dropped: &mut Vec<DroppedArg>) -> String {
+        let kept = self.filter(&split_options(options), source, dropped);
+        kept.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Splits the options the way the java launcher splits JDK_JAVA_OPTIONS, at whitespace outside of quotes.
+fn split_options(options: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    for c in options.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            None if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(char::is_whitespace) {
+        if arg.contains('"') {
+            return format!("'{}'", arg);
+        }
+        return format!("\"{}\"", arg);
+    }
+    arg.to_string()
+}
+
+fn check_bypass(arg: &str) -> Option<String> {
+    let arg = normalize(arg);
+    BYPASS_RULES
+        .iter()
+        .find(|(p, _)| matches(p, &arg))
+        .map(|(_, reason)| reason.to_string())
+}
+
+fn normalize(arg: &str) -> String {
+    arg.trim().to_lowercase()
+}
+
+fn matches(pattern: &str, arg: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => arg.starts_with(prefix),
+        None => arg == pattern,
+    }
+}
+
+/// whether an argument exists that matches both patterns
+fn overlaps(a: &str, b: &str) -> bool {
+    match (a.strip_suffix('*'), b.strip_suffix('*')) {
+        (Some(a), Some(b)) => a.starts_with(b) || b.starts_with(a),
+        (Some(prefix), None) => b.starts_with(prefix),
+        (None, Some(prefix)) => a.starts_with(prefix),
+        (None, None) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(policy: &ArgPolicy, args: &[&str]) -> (Vec<String>, Vec<String>) {
+        let mut dropped = Vec::new();
+        let kept = policy.filter(args, ArgSource::Connection, &mut dropped);
+        (kept, dropped.into_iter().map(|d| d.arg).collect())
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = ArgPolicy::default();
+        for arg in [
+            "-Xmx1g",
+            "-Dlog4j.skipJansi=true",
+            "--add-opens=java.base/java.lang=ALL-UNNAMED",
+            "-XX:+UseG1GC",
+            "-XX:-HeapDumpOnOutOfMemoryError",
+            "-Duser.language=en",
+        ] {
+            assert_eq!(None, policy.check(arg, ArgSource::Jnlp), "{}", arg);
+        }
+
+        for arg in [
+            "-javaagent:/tmp/agent.jar",
+            "-JAVAAGENT:/tmp/agent.jar",
+            " -javaagent:/tmp/agent.jar",
+            "-agentpath:/tmp/libagent.so",
+            "-agentlib:jdwp=transport=dt_socket,server=y",
+            "-Xrunjdwp:transport=dt_socket",
+            "-Xbootclasspath/a:/tmp/evil.jar",
+            "-XX:OnOutOfMemoryError=rm -rf ~",
+            "-XX:OnError=calc.exe",
+            "-noverify",
+            "-Xverify:none",
+            "--class-path=/tmp/evil.jar",
+            "--patch-module=java.base=/tmp/evil",
+            "-Djava.system.class.loader=Evil",
+            "-Djava.security.manager=allow",
+            "-Djava.security.manager",
+            "-Djava.security.policy==/tmp/all.policy",
+            "-Djava.class.path=/tmp/evil.jar",
+            "-Dlog4j.configurationFile=/opt/log4j2.xml",
+            "-Dlog4j.configuration=file:///tmp/log4j.properties",
+            "-Dlog4j2.configurationFile=https://evil.example.com/log4j2.xml",
+            "-XX:+HeapDumpOnOutOfMemoryError",
+            "-XX:HeapDumpPath=/tmp/shared",
+        ] {
+            assert!(policy.check(arg, ArgSource::Jnlp).is_some(), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn test_jnlp_defaults_leave_connections_alone() {
+        let policy = ArgPolicy::default();
+        let args = ["-Dlog4j.configurationFile=/opt/log4j2-debug.xml", "-XX:+HeapDumpOnOutOfMemoryError"];
+        let (kept, dropped) = filter(&policy, &args);
+        assert_eq!(args.to_vec(), kept);
+        assert!(dropped.is_empty());
+
+        let mut dropped = Vec::new();
+        assert!(policy.filter(&args, ArgSource::Jnlp, &mut dropped).is_empty());
+        assert_eq!(2, dropped.len());
+        // the rest of the default deny list applies to both
+        assert!(policy.check("-javaagent:/tmp/agent.jar", ArgSource::Connection).is_some());
+    }
+
+    #[test]
+    fn test_bypasses_are_always_dropped() {
+        let policy = ArgPolicy {
+            mode: PolicyMode::Denylist,
+            allow: vec![String::from("*")],
+            deny: vec![],
+        };
+        for arg in [
+            "@/tmp/args.txt",
+            "@args",
+            "-XX:+UnlockDiagnosticVMOptions",
+            "-xx:+unlockdiagnosticvmoptions",
+            "-XX:+UnlockExperimentalVMOptions",
+            "-XX:Flags=/tmp/.hotspotrc",
+            "-XX:VMOptionsFile=/tmp/options",
+        ] {
+            assert!(policy.check(arg, ArgSource::Jnlp).is_some(), "{}", arg);
+        }
+        // the allow list overrides the default deny list
+        assert_eq!(None, policy.check("-javaagent:/opt/apm/agent.jar", ArgSource::Jnlp));
+    }
+
+    #[test]
+    fn test_allow_and_deny_lists() {
+        let policy = ArgPolicy {
+            mode: PolicyMode::Denylist,
+            allow: vec![String::from("-javaagent:/opt/apm/*"), String::from("-Dacme.debug=true")],
+            deny: vec![String::from("-Dacme.*"), String::from("-XX:+HeapDumpOnOutOfMemoryError")],
+        };
+        assert_eq!(None, policy.check("-javaagent:/opt/apm/agent.jar", ArgSource::Jnlp));
+        assert!(policy.check("-javaagent:/tmp/agent.jar", ArgSource::Jnlp).is_some());
+        // the deny list wins over the allow list
+        assert!(policy.check("-Dacme.debug=true", ArgSource::Jnlp).is_some());
+        assert!(policy.check("-XX:+HeapDumpOnOutOfMemoryError", ArgSource::Jnlp).is_some());
+        assert_eq!(None, policy.check("-XX:-HeapDumpOnOutOfMemoryError", ArgSource::Jnlp));
+    }
+
+    #[test]
+    fn test_without_default_deny_overrides() {
+        let policy = ArgPolicy {
+            mode: PolicyMode::Denylist,
+            allow: vec![
+                String::from("-javaagent:/opt/apm/*"),
+                String::from("-AgentLib:*"),
+                String::from("-agent*"),
+                String::from("*"),
+                String::from("-noverify"),
+                String::from("-Dacme.*"),
+            ],
+            deny: vec![String::from("-Dacme.debug=*")],
+        }
+        .without_default_deny_overrides();
+        assert_eq!(vec!["-Dacme.*"], policy.allow);
+        assert!(policy.check("-javaagent:/opt/apm/agent.jar", ArgSource::Connection).is_some());
+        assert!(policy.check("-agentlib:jdwp=transport=dt_socket", ArgSource::Connection).is_some());
+        assert_eq!(None, policy.check("-Dacme.theme=dark", ArgSource::Connection));
+        assert!(policy.check("-Dacme.debug=true", ArgSource::Connection).is_some());
+    }
+
+    #[test]
+    fn test_allowlist_mode() {
+        let policy = ArgPolicy {
+            mode: PolicyMode::Allowlist,
+            allow: vec![String::from("-Dacme.*")],
+            deny: vec![],
+        };
+        assert_eq!(None, policy.check("-Xmx2g", ArgSource::Jnlp));
+        assert_eq!(None, policy.check("-Dacme.theme=dark", ArgSource::Jnlp));
+        assert_eq!(None, policy.check("--add-exports=java.base/com.sun.crypto.provider=ALL-UNNAMED", ArgSource::Jnlp));
+        assert_eq!(Some(String::from("not in the allow list")), policy.check("-XX:+UseZGC", ArgSource::Jnlp));
+        assert!(policy.check("-Dlog4j.configurationFile=/tmp/log4j2.xml", ArgSource::Jnlp).is_some());
+        assert!(policy.check("-javaagent:/tmp/agent.jar", ArgSource::Jnlp).is_some());
+        assert!(policy.check("@/tmp/args", ArgSource::Jnlp).is_some());
+    }
+
+    #[test]
+    fn test_options_with_values() {
+        let policy = ArgPolicy::default();
+        let (kept, dropped) = filter(
+            &policy,
+            &["-cp", "/tmp/evil.jar", "--add-opens", "java.base/java.lang=ALL-UNNAMED", "-Xmx1g", "-jar", "/tmp/evil.jar"],
+        );
+        assert_eq!(vec!["--add-opens", "java.base/java.lang=ALL-UNNAMED", "-Xmx1g"], kept);
+        assert_eq!(vec!["-cp /tmp/evil.jar", "-jar /tmp/evil.jar"], dropped);
+
+        // the value of an allowed option is not checked on its own, and a trailing option has no value
+        let (kept, dropped) = filter(&policy, &["--add-modules", "ALL-DEFAULT", "--add-opens", "@/tmp/args", "--module-path"]);
+        assert_eq!(vec!["--add-modules", "ALL-DEFAULT"], kept);
+        assert_eq!(vec!["--add-opens @/tmp/args", "--module-path"], dropped);
+    }
+
+    #[test]
+    fn test_filter_options() {
+        let policy = ArgPolicy::default();
+        let mut dropped = Vec::new();
+        let filtered = policy.filter_options(
+            "--add-modules=java.sql.rowset  \"-Dacme.title=Prod East\" '-javaagent:/tmp/a.jar' -XX:+UnlockDiagnosticVMOptions",
+            ArgSource::Jnlp,
+            &mut dropped,
+        );
+        assert_eq!("--add-modules=java.sql.rowset \"-Dacme.title=Prod East\"", filtered);
+        assert_eq!(
+            vec![
+                DroppedArg {
+                    arg: String::from("-javaagent:/tmp/a.jar"),
+                    source: ArgSource::Jnlp,
+                    reason: String::from("denied by default (-javaagent:*)"),
+                },
+                DroppedArg {
+                    arg: String::from("-XX:+UnlockDiagnosticVMOptions"),
+                    source: ArgSource::Jnlp,
+                    reason: String::from("unlocks VM options that are not covered by the policy"),
+                },
+            ],
+            dropped
+        );
+
+        // a quoted agent is still an agent
+        let mut dropped = Vec::new();
+        assert_eq!("", policy.filter_options("-java\"agent:/tmp/a b.jar\"", ArgSource::Jnlp, &mut dropped));
+        assert_eq!(1, dropped.len());
+        assert_eq!(vec!["a", "b c", "", "d"], split_options(" a 'b c' \"\" d "));
+    }
+
+    #[test]
+    fn test_load() {
+        let policy = ArgPolicy::load(Path::new("test-resources/policy/jvm-policy-allowlist.json")).unwrap();
+        assert_eq!(PolicyMode::Allowlist, policy.mode);
+        assert_eq!(None, policy.check("-Dmirth.client.theme=dark", ArgSource::Jnlp));
+        assert!(policy.check("-Dacme.debug=true", ArgSource::Jnlp).is_some());
+
+        let missing = ArgPolicy::load(Path::new("test-resources/policy/missing.json")).unwrap();
+        assert_eq!(PolicyMode::Denylist, missing.mode);
+        assert!(ArgPolicy::load(Path::new("test-resources/policy/jvm-policy-invalid.json")).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use reqwest::blocking::ClientBuilder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::connection::ConnectionEntry;
+use crate::fsutil::{read_json_or_restore, write_atomic, BACKUP_COUNT};
+use crate::import_sources::{parse_mcal_json, ImportWarning};
+
+/// A read-only list of connections maintained by someone else, e.g. the ops team.
+/// The location is either a path to a JSON file or an https URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogSource {
+    pub name: String,
+    pub location: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Catalog {
+    #[serde(flatten)]
+    pub source: CatalogSource,
+    #[serde(skip)]
+    pub entries: HashMap<String, Arc<ConnectionEntry>>,
+    /// set when the catalog could not be loaded, the last fetched copy is used if available
+    pub error: Option<String>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+pub fn read_catalog_sources(path: &Path) -> Result<Vec<CatalogSource>, Error> {
+    let sources: Option<Vec<CatalogSource>> = read_json_or_restore(path, BACKUP_COUNT)?;
+    Ok(sources.unwrap_or_default())
+}
+
+pub fn write_catalog_sources(path: &Path, sources: &[CatalogSource]) -> Result<(), Error> {
+    let val = serde_json::to_string_pretty(sources)?;
+    write_atomic(path, val.as_bytes(), BACKUP_COUNT)
+}
+
+/// Loads the catalog's entries, each successful fetch is kept in cache_dir and used when the location
+/// is not reachable. A remote catalog is only fetched if `refresh` is set, otherwise the cached copy is
+/// used so that starting the UI or running a command doesn't wait for the network.
+pub fn load_catalog(source: &CatalogSource, cache_dir: &Path, refresh: bool) -> Catalog {
+    let mut catalog = Catalog {
+        source: source.clone(),
+        entries: HashMap::new(),
+        error: None,
+        warnings: Vec::new(),
+    };
+
+    let location = source.location.trim();
+    if location.starts_with("http://") {
+        // the entries decide which server's code gets run, they must not be modifiable on the way
+        catalog.error = Some(String::from("catalogs must be fetched over https"));
+        return catalog;
+    }
+
+    let cached_copy = cache_dir.join(format!("{}.json", sanitize_name(&source.name)));
+    let data = if location.starts_with("https://") && !refresh {
+        fs::read_to_string(&cached_copy).ok()
+    } else {
+        match fetch(location) {
+            Ok(data) => {
+                if let Err(e) = fs::create_dir_all(cache_dir)
+                    .map_err(Error::new)
+                    .and_then(|_| write_atomic(&cached_copy, data.as_bytes(), 0))
+                {
+                    println!("failed to cache the catalog {}: {}", source.name, e);
+                }
+                Some(data)
+            }
+            Err(e) => {
+                println!("failed to load the catalog {} from {}: {}", source.name, source.location, e);
+                catalog.error = Some(e.to_string());
+                fs::read_to_string(&cached_copy).ok()
+            }
+        }
+    };
+
+    let Some(data) = data else {
+        return catalog;
+    };
+    match parse_mcal_json(&data) {
+        Ok(parsed) => {
+            catalog.warnings = parsed.warnings;
+            for mut ce in parsed.entries {
+                let key = if ce.id.is_empty() { ce.address.clone() } else { ce.id.clone() };
+                ce.id = catalog_entry_id(&source.name, &key);
+                ce.catalog = Some(source.name.clone());
+                // a catalog has no business setting the Java home of this machine, nor anything
+                // else that changes what runs on it, these can only be set by a local override
+                ce.java_home = String::new();
+                ce.password = None;
+                ce.java_args = None;
+                ce.env_vars.clear();
+                ce.system_properties.clear();
+                ce.working_dir = None;
+                ce.verify = true;
+                catalog.entries.insert(ce.id.clone(), Arc::new(ce));
+            }
+        }
+        Err(e) => {
+            catalog.error = Some(format!("invalid catalog: {}", e));
+        }
+    }
+    catalog
+}
+
+/// Applies the fields a user is allowed to change on a catalog entry.
+/// The address, name and group always come from the catalog and the jars are always verified.
+pub fn apply_override(entry: &ConnectionEntry, local: &ConnectionEntry) -> ConnectionEntry {
+    let mut merged = entry.clone();
+    merged.username = local.username.clone();
+    merged.password = local.password.clone();
+    merged.heap_size = local.heap_size.clone();
+    merged.java_home = local.java_home.clone();
+    merged.java_args = local.java_args.clone();
+    merged.show_console = local.show_console;
+    merged.donotcache = local.donotcache;
+    merged.last_connected = local.last_connected;
+    merged.env_vars = local.env_vars.clone();
+    merged.system_properties = local.system_properties.clone();
+    merged.working_dir = local.working_dir.clone();
+    merged.notes = local.notes.clone();
+    merged
+}
+
+fn fetch(location: &str) -> Result<String, Error> {
+    if location.starts_with("https://") {
+        let client = ClientBuilder::default()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let resp = client.get(location).send()?.error_for_status()?;
+        return Ok(resp.text()?);
+    }
+    Ok(fs::read_to_string(location)?)
+}
+
+/// Derived from the catalog's id of the entry, or its address if it has none, so that the local overrides
+/// survive reloads. Namespaced by the catalog name, a catalog can't pick an id to replace a local connection
+/// or an entry of another catalog.
+fn catalog_entry_id(catalog_name: &str, key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(catalog_name.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(key.trim().to_lowercase().as_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Uuid::from_bytes(bytes).to_string()
+}
+
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_catalog() {
+        let cache_dir = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        let source = CatalogSource {
+            name: String::from("ops"),
+            location: String::from("test-resources/catalog/ops-engines.json"),
+        };
+        let catalog = load_catalog(&source, &cache_dir, true);
+        assert_eq!(None, catalog.error);
+        assert_eq!(2, catalog.entries.len());
+        assert!(catalog.entries.values().all(|ce| ce.catalog.as_deref() == Some("ops")));
+        // the catalog's id is not used as is
+        assert!(!catalog.entries.contains_key("0c0a7a10-0000-4000-8000-00000000c001"));
+
+        // nothing that changes what runs on this machine is taken from the catalog
+        let west = catalog.entries.values().find(|ce| ce.name == "Prod West").unwrap();
+        assert!(west.verify);
+        assert_eq!(None, west.java_args);
+        assert!(west.env_vars.is_empty());
+        assert!(west.system_properties.is_empty());
+        assert_eq!(None, west.working_dir);
+
+        // the entry without an id gets the same id every time
+        let again = load_catalog(&source, &cache_dir, true);
+        let mut ids: Vec<&String> = catalog.entries.keys().collect();
+        let mut ids_again: Vec<&String> = again.entries.keys().collect();
+        ids.sort();
+        ids_again.sort();
+        assert_eq!(ids, ids_again);
+
+        // the last fetched copy is used when the location is gone
+        let missing = CatalogSource {
+            name: String::from("ops"),
+            location: String::from("test-resources/catalog/missing.json"),
+        };
+        let cached = load_catalog(&missing, &cache_dir, true);
+        assert!(cached.error.is_some());
+        assert_eq!(2, cached.entries.len());
+
+        // a remote catalog is read from the cached copy unless refreshed
+        let remote = CatalogSource {
+            name: String::from("ops"),
+            location: String::from("https://catalog.invalid/ops.json"),
+        };
+        let cached = load_catalog(&remote, &cache_dir, false);
+        assert_eq!(None, cached.error);
+        assert_eq!(2, cached.entries.len());
+
+        let plain = CatalogSource {
+            name: String::from("ops"),
+            location: String::from("http://catalog.example.com/ops.json"),
+        };
+        let rejected = load_catalog(&plain, &cache_dir, true);
+        assert!(rejected.error.is_some());
+        assert!(rejected.entries.is_empty());
+        fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_override() {
+        let entry = ConnectionEntry {
+            id: String::from("1"),
+            name: String::from("prod"),
+            address: String::from("https://prod.example.com:8443"),
+            catalog: Some(String::from("ops")),
+            java_home: String::new(),
+            ..Default::default()
+        };
+        let local = ConnectionEntry {
+            id: String::from("1"),
+            name: String::from("renamed"),
+            address: String::from("https://evil.example.com"),
+            username: Some(String::from("me")),
+            heap_size: String::from("2g"),
+            java_home: String::from("/opt/java"),
+            ..Default::default()
+        };
+        let merged = apply_override(&entry, &local);
+        assert_eq!("prod", merged.name);
+        assert_eq!("https://prod.example.com:8443", merged.address);
+        assert_eq!(Some("me"), merged.username.as_deref());
+        assert_eq!("2g", merged.heap_size);
+        assert_eq!("/opt/java", merged.java_home);
+        assert_eq!(Some("ops"), merged.catalog.as_deref());
+    }
+}
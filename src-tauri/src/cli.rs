@@ -0,0 +1,423 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Error;
+use openssl::x509::X509;
+use tauri::ipc::{Channel, InvokeResponseBody};
+
+use crate::connection::{
+    cert_fingerprint, create_cert_store, parse_trusted_certs, read_verify_settings, ConnectionEntry, ConnectionStore,
+    ExportOptions, SecretHandling,
+};
+use crate::errors::format_name;
+use crate::importer::{ImportOptions, MergeStrategy};
+use crate::profile::ProfileLocation;
+use crate::verify::{verify_jar_with_report, JarReport, RevocationCheck};
+use crate::webstart::{is_jnlp_location, WebstartFile};
+
+const SUBCOMMANDS: &[&str] = &["list", "launch", "import", "export", "cache", "trust", "verify", "help"];
+
+/// read instead of a command line option so that it doesn't show up in the process list
+const PASSPHRASE_ENV: &str = "BALLISTA_PASSPHRASE";
+
+const USAGE: &str = "usage: ballista [--profile <name>] <command>
+
+commands:
+  list [--json]                       list the connections
+  launch <name|id|jnlp>               launch the administrator of a connection or of a JNLP file or URL
+  import <file> [--dry-run] [--strategy keep_mine|take_theirs|keep_both] [--keep-java-home]
+                                      import connections from a Ballista/MCAL JSON, CSV file or a directory of JNLP files
+  export <file> [--group <group>] [--id <id>]... [--passwords strip|encrypt|include] [--exclude-machine-fields]
+                                      export connections, the passwords are stripped by default,
+                                      encrypted passwords can only be imported by Ballista, not by MCAL
+  cache prune                         remove the downloaded files of the connections that no longer exist
+  trust list                          list the trusted certificates
+  verify <jar|dir> [--trusted <certs.json>] [--allow-weak-digests] [--revocation <off|soft_fail|hard_fail>] [--crl-dir <dir>] [--json]
+                                      verify the signatures of a jar or of all the jars in a directory,
+                                      certs.json has the format of ballista-trusted-certs.json
+  help                                show this message
+
+the passphrase for encrypted passwords is read from the BALLISTA_PASSPHRASE environment variable";
+
+pub struct Args {
+    pub profile: Option<String>,
+    /// empty when the UI should be started
+    pub command: Vec<String>,
+}
+
+/// Splits out --profile <name> or --profile=<name>, the remaining args are a command only if
+/// they start with a known subcommand, anything else is left to the UI.
+pub fn parse_args(args: impl Iterator<Item = String>) -> Args {
+    let mut profile = None;
+    let mut rest = Vec::new();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            profile = args.next();
+        } else if let Some(name) = arg.strip_prefix("--profile=") {
+            profile = Some(name.to_string());
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    let is_command = rest.first().is_some_and(|c| SUBCOMMANDS.contains(&c.as_str()));
+    Args {
+        profile,
+        command: if is_command { rest } else { Vec::new() },
+    }
+}
+
+/// Runs the command and returns the process exit code. The store of the profile is opened, and migrated,
+/// only by the commands that work with the connections, help and verify only read files.
+pub fn run(command: &[String], profile: &ProfileLocation) -> i32 {
+    let r = match command[0].as_str() {
+        "list" => profile.open().and_then(|cs| list(&command[1..], &cs)),
+        "launch" => profile.open().and_then(|cs| launch(&command[1..], &cs)),
+        "import" => profile.open().and_then(|cs| import(&command[1..], &cs)),
+        "export" => profile.open().and_then(|cs| export(&command[1..], &cs)),
+        "cache" if command.get(1).map(String::as_str) == Some("prune") => {
+            profile.open().and_then(|cs| prune_cache(&cs))
+        }
+        "trust" if command.get(1).map(String::as_str) == Some("list") => {
+            profile.open().and_then(|cs| list_trusted_certs(&cs))
+        }
+        "verify" => verify(&command[1..], profile),
+        "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(Error::msg(USAGE)),
+    };
+
+    match r {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn list(args: &[String], cs: &ConnectionStore) -> Result<(), Error> {
+    let all = cs.list();
+    if args.iter().any(|a| a == "--json") {
+        // the passwords are stripped like export does by default, the output often ends up in scripts and logs
+        let all: Vec<ConnectionEntry> = all
+            .iter()
+            .map(|ce| ConnectionEntry {
+                password: None,
+                ..ConnectionEntry::clone(ce)
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&all)?);
+        return Ok(());
+    }
+
+    for ce in all {
+        println!("{:<36}  {:<20}  {:<30}  {}", ce.id, ce.group, ce.name, ce.address);
+    }
+    Ok(())
+}
+
+fn launch(args: &[String], cs: &ConnectionStore) -> Result<(), Error> {
+    let name_or_id = args.first().ok_or_else(|| Error::msg("usage: ballista launch <name|id|jnlp>"))?;
+    // a JNLP file or URL can be launched without saving it first
+    let ce = match cs.find(name_or_id) {
+        Ok(ce) => ce,
+        Err(_) if is_jnlp_location(name_or_id) => cs.add_temporary(name_or_id)?,
+        Err(e) => return Err(e),
+    };
+    let mut ce = cs.enforce(ce)?;
+    if ce.show_console {
+        // the console jar is a resource of the app, which is not set up for the command line
+        eprintln!("warning: the Java console is not shown for connections launched from the command line");
+        Arc::make_mut(&mut ce).show_console = false;
+    }
+
+    let on_progress = Channel::new(|body| {
+        if let InvokeResponseBody::Json(msg) = body {
+            if let Ok(msg) = serde_json::from_str::<serde_json::Value>(&msg) {
+                if let Some(msg) = msg["message"].as_str() {
+                    println!("{}", msg);
+                }
+            }
+        }
+        Ok(())
+    });
+    let ws = WebstartFile::load(&ce.address, &cs.cache_dir, ce.donotcache, &ce.id, &on_progress)?;
+    if ce.verify {
+        println!("Verifying jar signatures...");
+        let warnings = ws
+            .verify(
+                cs.get_cert_store().as_ref(),
+                &cs.get_trusted_certs(),
+                &cs.get_verify_policy()?,
+                Some(&cs.get_verify_cache_location()),
+            )
+            .map_err(|e| match e.cert {
+                // trusting requires looking at the certificate, that is left to the UI
+                Some(ref cert) => Error::msg(format!(
+                    "{}\nsigner: {}\ntrust the certificate from the Ballista UI to launch this connection",
+                    e.msg,
+                    format_name(cert.subject_name())
+                )),
+                None => Error::msg(e.msg),
+            })?;
+        for w in warnings {
+            eprintln!("warning: {}", w);
+        }
+    }
+
+    let policy = cs.get_arg_policy()?;
+    println!("Launching administrator...");
+    let id = ce.id.clone();
+    for d in ws.run(ce, None, &policy)? {
+        eprintln!("dropped JVM argument {}: {}", d.arg, d.reason);
+    }
+    cs.update_last_connected(&id)
+}
+
+fn import(args: &[String], cs: &ConnectionStore) -> Result<(), Error> {
+    let mut file_path = None;
+    let mut options = ImportOptions {
+        passphrase: std::env::var(PASSPHRASE_ENV).ok(),
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => options.dry_run = true,
+            "--keep-java-home" => options.keep_java_home = true,
+            "--strategy" => {
+                let strategy = option_value(arg, args.next())?;
+                let strategy: MergeStrategy = serde_json::from_value(serde_json::Value::String(strategy.clone()))
+                    .map_err(|_| Error::msg(format!("invalid strategy {}", strategy)))?;
+                options.default_strategy = Some(strategy);
+            }
+            _ if file_path.is_none() && !arg.starts_with("--") => file_path = Some(arg),
+            _ => return Err(Error::msg(format!("unknown option {}", arg))),
+        }
+    }
+
+    let file_path = file_path.ok_or_else(|| Error::msg("usage: ballista import <file>"))?;
+    let result = cs.import(file_path, &options)?;
+    let result: serde_json::Value = serde_json::from_str(&result)?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    match result["status"].as_str() {
+        Some("passphrase_required") => Err(Error::msg(format!(
+            "the passwords are encrypted, set the passphrase in the {} environment variable",
+            PASSPHRASE_ENV
+        ))),
+        Some("conflicts") => Err(Error::msg(
+            "some connections already exist, choose how to merge them with --strategy",
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn export(args: &[String], cs: &ConnectionStore) -> Result<(), Error> {
+    let mut file_path = None;
+    let mut group = None;
+    let mut ids = Vec::new();
+    let mut options = ExportOptions {
+        secrets: SecretHandling::Strip,
+        passphrase: std::env::var(PASSPHRASE_ENV).ok(),
+        exclude_machine_fields: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--group" => group = Some(option_value(arg, args.next())?),
+            "--id" => ids.push(option_value(arg, args.next())?.clone()),
+            "--exclude-machine-fields" => options.exclude_machine_fields = true,
+            "--passwords" => {
+                let secrets = option_value(arg, args.next())?;
+                options.secrets = serde_json::from_value(serde_json::Value::String(secrets.clone()))
+                    .map_err(|_| Error::msg(format!("invalid value for --passwords {}", secrets)))?;
+            }
+            _ if file_path.is_none() && !arg.starts_with("--") => file_path = Some(arg),
+            _ => return Err(Error::msg(format!("unknown option {}", arg))),
+        }
+    }
+
+    let file_path = file_path.ok_or_else(|| Error::msg("usage: ballista export <file>"))?;
+    if options.secrets == SecretHandling::Encrypt && options.passphrase.is_none() {
+        return Err(Error::msg(format!(
+            "set the passphrase to encrypt the passwords in the {} environment variable",
+            PASSPHRASE_ENV
+        )));
+    }
+    let ids = if ids.is_empty() { None } else { Some(ids.as_slice()) };
+    let result = cs.export(ids, group.map(String::as_str), file_path, &options)?;
+    println!("{}", result);
+    Ok(())
+}
+
+fn prune_cache(cs: &ConnectionStore) -> Result<(), Error> {
+    let removed: Vec<PathBuf> = cs.prune_cache()?;
+    println!("removed {} cache directories", removed.len());
+    Ok(())
+}
+
+fn list_trusted_certs(cs: &ConnectionStore) -> Result<(), Error> {
+    for cert in cs.get_trusted_certs() {
+        println!("{}", cert_fingerprint(&cert)?);
+        println!("  subject: {}", format_name(cert.subject_name()));
+        println!("  issuer:  {}", format_name(cert.issuer_name()));
+        println!("  expires: {}", cert.not_after());
+    }
+    Ok(())
+}
+
+fn verify(args: &[String], profile: &ProfileLocation) -> Result<(), Error> {
+    let mut path = None;
+    let mut trusted_file = None;
+    let mut json = false;
+    let (mut policy, profile_certs) = read_verify_settings(&profile.data_dir()?)?;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trusted" => trusted_file = Some(option_value(arg, args.next())?),
+            "--json" => json = true,
+            "--allow-weak-digests" => policy.allow_weak_digests = true,
+            "--revocation" => {
+                policy.revocation = match option_value(arg, args.next())?.as_str() {
+                    "off" => RevocationCheck::Off,
+                    "soft_fail" => RevocationCheck::SoftFail,
+                    "hard_fail" => RevocationCheck::HardFail,
+                    mode => return Err(Error::msg(format!("unknown revocation mode {}", mode))),
+                }
+            }
+            "--crl-dir" => policy.crl_dir = Some(PathBuf::from(option_value(arg, args.next())?)),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(Error::msg(format!("unknown option {}", arg))),
+        }
+    }
+    let path = Path::new(path.ok_or_else(|| Error::msg("usage: ballista verify <jar|dir>"))?);
+
+    let (cert_store, trusted_certs) = match trusted_file {
+        Some(f) => {
+            let f = Path::new(f);
+            if !f.is_file() {
+                return Err(Error::msg(format!("{:?} does not exist", f)));
+            }
+            let certs = parse_trusted_certs(f);
+            let trusted_certs: Vec<X509> = certs.values().cloned().collect();
+            (Arc::new(create_cert_store(certs)), trusted_certs)
+        }
+        None => {
+            let trusted_certs: Vec<X509> = profile_certs.values().cloned().collect();
+            (Arc::new(create_cert_store(profile_certs)), trusted_certs)
+        }
+    };
+
+    let mut jars = Vec::new();
+    if path.is_dir() {
+        find_jars(path, &mut jars)?;
+        jars.sort();
+    } else {
+        jars.push(path.to_path_buf());
+    }
+
+    let mut reports = Vec::with_capacity(jars.len());
+    for jar in jars {
+        let mut report = JarReport::default();
+        let _ = verify_jar_with_report(&jar.to_string_lossy(), cert_store.as_ref(), &trusted_certs, &policy, &mut report);
+        reports.push(report);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for r in &reports {
+            println!("{}: {}", r.jar, if r.failures.is_empty() { "verified" } else { "FAILED" });
+            for signer in &r.signers {
+                println!("  signature file: {}", signer.signature_file);
+                println!("    subject:  {}", signer.subject.as_deref().unwrap_or("-"));
+                println!("    issuer:   {}", signer.issuer.as_deref().unwrap_or("-"));
+                println!(
+                    "    validity: {} - {}",
+                    signer.not_before.as_deref().unwrap_or("-"),
+                    signer.not_after.as_deref().unwrap_or("-")
+                );
+                println!("    digest:   {}", signer.digest_algorithm.as_deref().unwrap_or("-"));
+                println!("    signed:   {}", signer.signing_time.as_deref().unwrap_or("not timestamped"));
+            }
+            println!("  entries checked: {}", r.entries_checked);
+            for w in &r.warnings {
+                println!("  warning: {}", w);
+            }
+            for f in &r.failures {
+                println!("  failure: {}", f);
+            }
+        }
+    }
+
+    let failed = reports.iter().filter(|r| !r.failures.is_empty()).count();
+    if failed > 0 {
+        return Err(Error::msg(format!("{} of {} jars failed verification", failed, reports.len())));
+    }
+    Ok(())
+}
+
+/// Symbolic links to directories are not followed, they could point back to a parent and loop forever.
+fn find_jars(dir: &Path, jars: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for e in dir.read_dir()? {
+        let path = e?.path();
+        if std::fs::symlink_metadata(&path)?.is_dir() {
+            find_jars(&path, jars)?;
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("jar")) {
+            jars.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn option_value<'a>(option: &str, value: Option<&'a String>) -> Result<&'a String, Error> {
+    value.ok_or_else(|| Error::msg(format!("missing value for {}", option)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Args {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = to_args(&["--profile", "prod", "launch", "engine-a"]);
+        assert_eq!(Some("prod"), args.profile.as_deref());
+        assert_eq!(vec!["launch", "engine-a"], args.command);
+
+        let args = to_args(&["list", "--profile=staging"]);
+        assert_eq!(Some("staging"), args.profile.as_deref());
+        assert_eq!(vec!["list"], args.command);
+
+        // not a command, e.g. an argument passed by the OS, starts the UI
+        let args = to_args(&["-psn_0_12345"]);
+        assert!(args.command.is_empty());
+        assert!(to_args(&[]).command.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_jars_does_not_follow_directory_links() {
+        let dir = std::env::temp_dir().join(format!("ballista-find-jars-{}", uuid::Uuid::new_v4()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::copy("test-resources/valid-signed.jar", lib.join("app.jar")).unwrap();
+        std::os::unix::fs::symlink(&dir, lib.join("loop")).unwrap();
+
+        let mut jars = Vec::new();
+        find_jars(&dir, &mut jars).unwrap();
+        assert_eq!(vec![lib.join("app.jar")], jars);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
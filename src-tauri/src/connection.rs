@@ -5,21 +5,33 @@
 use anyhow::Error;
 use home::env::Env;
 use home::env::OS_ENV;
+use openssl::hash::MessageDigest;
 use openssl::x509::store::{X509Store, X509StoreBuilder};
 use openssl::x509::X509;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::ops::Deref;
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
+use crate::argpolicy::ArgPolicy;
+use crate::errors::FieldError;
+use crate::managed::{system_policy_path, ManagedPolicy};
+use crate::catalog::{apply_override, load_catalog, read_catalog_sources, write_catalog_sources, Catalog, CatalogSource};
+use crate::fsutil::{read_json_or_restore, write_atomic, BACKUP_COUNT};
+use crate::import_sources::{read_import_source, ParsedImport};
+use crate::importer::{plan_import, ImportOptions};
+use crate::secrets::{is_encrypted, SecretDecrypter, SecretEncrypter};
+use crate::validate::{format_errors, validate_connection, validate_java_home};
+use crate::verify::VerifyPolicy;
+use crate::webstart::{connection_cache_dir, is_jnlp_location, normalize_url};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionEntry {
     pub address: String,
@@ -46,6 +58,71 @@ pub struct ConnectionEntry {
     pub last_connected: Option<i64>,
     #[serde(default, rename = "showConsole")]
     pub show_console: bool,
+    /// environment variables set on the launched process, applied after the JNLP's JDK_JAVA_OPTIONS
+    #[serde(default, rename = "envVars")]
+    pub env_vars: BTreeMap<String, String>,
+    /// passed as -Dkey=value, before the free-text java_args
+    #[serde(default, rename = "systemProperties")]
+    pub system_properties: BTreeMap<String, String>,
+    #[serde(default, rename = "workingDir")]
+    pub working_dir: Option<String>,
+    /// name of the read-only catalog this connection comes from, never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog: Option<String>,
+}
+
+/// how the passwords are written while exporting
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretHandling {
+    Strip,
+    /// only Ballista can read the encrypted passwords, MCAL sees them as the literal enc:v1:... strings
+    Encrypt,
+    Include,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportOptions {
+    pub secrets: SecretHandling,
+    /// required when secrets is SecretHandling::Encrypt
+    pub passphrase: Option<String>,
+    /// leaves out java_home, working_dir and last_connected which only make sense on this machine
+    #[serde(default)]
+    pub exclude_machine_fields: bool,
+}
+
+/// How the downloaded files of a profile's connections are kept, read from ballista-cache-policy.json.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CachePolicy {
+    /// the jars are downloaded again on every launch, as if donotcache was set on all the connections
+    #[serde(default)]
+    pub donotcache: bool,
+    /// the files of the connections not launched for this many days are removed when the cache is pruned
+    #[serde(default)]
+    pub max_unused_days: Option<u32>,
+}
+
+impl CachePolicy {
+    /// The default policy is used when the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(CachePolicy::default());
+        }
+        let f = File::open(path)?;
+        serde_json::from_reader(std::io::BufReader::new(f))
+            .map_err(|e| Error::msg(format!("invalid cache policy {:?}: {}", path, e)))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReloadOutcome {
+    Unchanged,
+    Reloaded,
+    /// the file was changed but the in-memory state has changes that were never written
+    Conflict,
+    /// the file was changed but could not be parsed
+    Invalid(String),
 }
 
 pub struct ConnectionStore {
@@ -54,6 +131,41 @@ pub struct ConnectionStore {
     pub cache_dir: PathBuf,
     cert_store: Mutex<Arc<X509Store>>,
     trusted_certs_location: PathBuf,
+    /// SHA-256 of the data file's contents when it was last read or written
+    last_synced: Mutex<Option<String>>,
+    /// set when the in-memory connections could not be written to the data file
+    unsaved: AtomicBool,
+    /// not written to disk, lock only after con_cache when both are needed
+    temporary: Mutex<HashMap<String, Arc<ConnectionEntry>>>,
+    /// lock only after con_cache when both are needed
+    catalogs: Mutex<Vec<Catalog>>,
+    catalog_sources_location: PathBuf,
+    catalog_cache_dir: PathBuf,
+    crl_cache_dir: PathBuf,
+    verify_cache_location: PathBuf,
+    jvm_policy_location: PathBuf,
+    verify_policy_location: PathBuf,
+    cache_policy_location: PathBuf,
+    managed: Option<ManagedPolicy>,
+    /// finds the Java home of the connections saved without one
+    find_java_home: fn() -> String,
+}
+
+/// What a store reads from the machine rather than from its data directory,
+/// the tests replace it so that they don't depend on the machine they run on.
+#[derive(Clone)]
+pub struct MachineSettings {
+    pub policy_path: PathBuf,
+    pub find_java_home: fn() -> String,
+}
+
+impl MachineSettings {
+    pub fn system() -> Self {
+        MachineSettings {
+            policy_path: system_policy_path(),
+            find_java_home,
+        }
+    }
 }
 
 impl Default for ConnectionEntry {
@@ -75,34 +187,36 @@ impl Default for ConnectionEntry {
             donotcache: get_default_donotcache(),
             last_connected: None,
             show_console: false,
+            env_vars: BTreeMap::new(),
+            system_properties: BTreeMap::new(),
+            working_dir: None,
+            catalog: None,
         }
     }
 }
 
 impl ConnectionStore {
-    pub fn init(data_dir_path: PathBuf) -> Result<Self, Error> {
-        let con_location = data_dir_path.join("ballista-data.json");
-        let mut con_location_file = File::open(&con_location);
-        if let Err(_e) = con_location_file {
-            con_location_file = File::create(&con_location);
-        }
-        let con_location_file = con_location_file?;
+    pub fn init(data_dir_path: PathBuf, machine: &MachineSettings) -> Result<Self, Error> {
+        let managed = ManagedPolicy::load(&machine.policy_path)?;
+        let mut cs = Self::init_with_policy(data_dir_path, managed)?;
+        cs.find_java_home = machine.find_java_home;
+        Ok(cs)
+    }
 
+    pub fn init_with_policy(data_dir_path: PathBuf, managed: Option<ManagedPolicy>) -> Result<Self, Error> {
+        let con_location = data_dir_path.join("ballista-data.json");
         let mut cache = HashMap::new();
-        let data: serde_json::Result<HashMap<String, ConnectionEntry>> =
-            serde_json::from_reader(con_location_file);
-        match data {
-            Ok(data) => {
-                for (id, ce) in data {
-                    cache.insert(id, Arc::new(ce));
-                }
-            }
-            Err(e) => {
-                println!("{}", e);
+        let data: Option<HashMap<String, ConnectionEntry>> = read_json_or_restore(&con_location, BACKUP_COUNT)?;
+        if let Some(data) = data {
+            for (id, ce) in data {
+                cache.insert(id, Arc::new(ce));
             }
         }
+        let last_synced = fs::read(&con_location).ok().map(|data| sha256_hex(&data));
 
         let trusted_certs_location = data_dir_path.join("ballista-trusted-certs.json");
+        // only to quarantine and restore a corrupt file, the certs are parsed below
+        let _: Option<FxHashMap<String, String>> = read_json_or_restore(&trusted_certs_location, BACKUP_COUNT)?;
         let certs = parse_trusted_certs(&trusted_certs_location);
         let cert_store = create_cert_store(certs);
 
@@ -111,17 +225,62 @@ impl ConnectionStore {
             fs::create_dir(&cache_dir)?;
         }
 
+        let catalog_sources_location = data_dir_path.join("ballista-catalogs.json");
+        let catalog_cache_dir = cache_dir.join("catalogs");
+        let crl_cache_dir = cache_dir.join("crls");
+        let verify_cache_location = cache_dir.join("verified-jars.json");
+        let catalogs = read_catalog_sources(&catalog_sources_location)?
+            .iter()
+            .map(|source| load_catalog(source, &catalog_cache_dir, false))
+            .collect();
+
         Ok(ConnectionStore {
             con_location,
             con_cache: Mutex::new(cache),
+            last_synced: Mutex::new(last_synced),
+            unsaved: AtomicBool::new(false),
+            temporary: Mutex::new(HashMap::new()),
             cert_store: Mutex::new(Arc::new(cert_store)),
             trusted_certs_location,
-            cache_dir
+            cache_dir,
+            catalogs: Mutex::new(catalogs),
+            catalog_sources_location,
+            catalog_cache_dir,
+            crl_cache_dir,
+            verify_cache_location,
+            jvm_policy_location: data_dir_path.join("ballista-jvm-policy.json"),
+            verify_policy_location: data_dir_path.join("ballista-verify-policy.json"),
+            cache_policy_location: data_dir_path.join("ballista-cache-policy.json"),
+            managed,
+            find_java_home,
         })
     }
 
+    /// the local connections and the entries of all the catalogs with their local overrides applied
+    fn merged(&self) -> HashMap<String, Arc<ConnectionEntry>> {
+        let local = self.con_cache.lock().expect("connection cache lock poisoned");
+        let catalogs = self.catalogs.lock().expect("catalogs lock poisoned");
+        let mut merged = local.clone();
+        // the ids of the catalog entries are namespaced by the catalog, a local entry with the same id is an override
+        for c in catalogs.iter() {
+            for (id, entry) in &c.entries {
+                let ce = match local.get(id) {
+                    Some(l) => Arc::new(apply_override(entry, l)),
+                    None => Arc::clone(entry),
+                };
+                merged.insert(id.clone(), ce);
+            }
+        }
+        merged
+    }
+
+    fn get_catalog_entry(&self, id: &str) -> Option<Arc<ConnectionEntry>> {
+        let catalogs = self.catalogs.lock().expect("catalogs lock poisoned");
+        catalogs.iter().find_map(|c| c.entries.get(id).cloned())
+    }
+
     pub fn to_json_array_string(&self) -> String {
-        let cache = self.con_cache.lock().expect("connection cache lock poisoned");
+        let cache = self.merged();
         let mut sb = String::with_capacity(1024);
         let len = cache.len();
         sb.push('[');
@@ -137,105 +296,376 @@ impl ConnectionStore {
         sb
     }
 
+    /// all connections sorted by group and name
+    pub fn list(&self) -> Vec<Arc<ConnectionEntry>> {
+        let mut all: Vec<Arc<ConnectionEntry>> = self.merged().into_values().collect();
+        all.sort_by(|a, b| a.group.cmp(&b.group).then_with(|| a.name.cmp(&b.name)));
+        all
+    }
+
+    /// Finds a connection by its id, or by its name ignoring the case.
+    pub fn find(&self, name_or_id: &str) -> Result<Arc<ConnectionEntry>, Error> {
+        if let Some(ce) = self.get(name_or_id) {
+            return Ok(ce);
+        }
+        let mut matches: Vec<Arc<ConnectionEntry>> = self
+            .list()
+            .into_iter()
+            .filter(|ce| ce.name.eq_ignore_ascii_case(name_or_id))
+            .collect();
+        match matches.len() {
+            0 => Err(Error::msg(format!("connection not found: {}", name_or_id))),
+            1 => Ok(matches.remove(0)),
+            n => Err(Error::msg(format!(
+                "{} connections are named {}, use the id instead",
+                n, name_or_id
+            ))),
+        }
+    }
+
+    /// Removes the downloaded jars of the connections that no longer exist, or that were not launched
+    /// for longer than the cache policy allows, and returns the removed directories.
+    pub fn prune_cache(&self) -> Result<Vec<PathBuf>, Error> {
+        let policy = self.get_cache_policy()?;
+        let oldest = policy.max_unused_days.map(|days| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("system clock is before UNIX epoch")
+                .as_millis() as i64;
+            now - i64::from(days) * 24 * 60 * 60 * 1000
+        });
+        let kept: HashSet<PathBuf> = self
+            .merged()
+            .values()
+            .filter(|ce| match (oldest, ce.last_connected) {
+                (Some(oldest), Some(last_connected)) => last_connected >= oldest,
+                _ => true,
+            })
+            .map(|ce| connection_cache_dir(&self.cache_dir, &ce.id))
+            .collect();
+
+        let mut removed = Vec::new();
+        for e in self.cache_dir.read_dir()? {
+            let e = e?;
+            let path = e.path();
+            if !e.metadata()?.is_dir() || path == self.catalog_cache_dir || path == self.crl_cache_dir {
+                continue;
+            }
+            if !kept.contains(&path) {
+                println!("removing cache directory {:?}", path);
+                fs::remove_dir_all(&path)?;
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+
     pub fn get(&self, id: &str) -> Option<Arc<ConnectionEntry>> {
         let cs = self.con_cache.lock().expect("connection cache lock poisoned");
         let val = cs.get(id);
+        if let Some(entry) = self.get_catalog_entry(id) {
+            return match val {
+                Some(local) => Some(Arc::new(apply_override(&entry, local))),
+                None => Some(entry),
+            };
+        }
         if let Some(val) = val {
             return Some(Arc::clone(val));
         }
-        None
+        let temporary = self.temporary.lock().expect("temporary connections lock poisoned");
+        temporary.get(id).cloned()
+    }
+
+    /// Returns the saved connection with the given address, or creates a connection that is only kept
+    /// in memory, e.g. for an address received through a ballista://open link or a JNLP file that was opened.
+    pub fn add_temporary(&self, address: &str) -> Result<Arc<ConnectionEntry>, Error> {
+        let address = address.trim();
+        if is_jnlp_location(address) {
+            let path = match reqwest::Url::parse(address) {
+                // kept as it is, the query string may select a tenant or carry a token
+                Ok(u) if matches!(u.scheme(), "http" | "https") => {
+                    let name = u
+                        .host_str()
+                        .filter(|h| !h.is_empty())
+                        .ok_or_else(|| Error::msg(format!("missing host in {}", address)))?
+                        .to_string();
+                    let existing = self.merged().into_values().find(|ce| ce.address.trim() == address);
+                    if let Some(ce) = existing {
+                        return Ok(ce);
+                    }
+                    return self.insert_temporary(address.to_string(), name);
+                }
+                Ok(u) if u.scheme() == "file" => u
+                    .to_file_path()
+                    .map_err(|_| Error::msg(format!("invalid file URL {}", address)))?,
+                _ => PathBuf::from(address),
+            };
+            let path = fs::canonicalize(path)?;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            return self.insert_temporary(path.to_string_lossy().to_string(), name);
+        }
+
+        let (address, _host) = normalize_url(address)?;
+        let existing = self
+            .merged()
+            .into_values()
+            .find(|ce| normalize_url(&ce.address).is_ok_and(|(a, _)| a == address));
+        if let Some(ce) = existing {
+            return Ok(ce);
+        }
+
+        let name = reqwest::Url::parse(&address)?.host_str().unwrap_or_default().to_string();
+        self.insert_temporary(address, name)
+    }
+
+    /// The id is derived from the address so that opening the same address or file again reuses its cache directory.
+    fn insert_temporary(&self, address: String, name: String) -> Result<Arc<ConnectionEntry>, Error> {
+        let ce = self.enforce(Arc::new(ConnectionEntry {
+            id: temporary_id(&address),
+            address,
+            name,
+            group: String::from("Temporary"),
+            ..Default::default()
+        }))?;
+        self.temporary
+            .lock()
+            .expect("temporary connections lock poisoned")
+            .insert(ce.id.clone(), Arc::clone(&ce));
+        Ok(ce)
+    }
+
+    /// Applies the cache policy of the profile and the managed policy to a connection before it is launched,
+    /// the connections saved before the managed policy was installed and those of the catalogs may not comply with it.
+    pub fn enforce(&self, ce: Arc<ConnectionEntry>) -> Result<Arc<ConnectionEntry>, Error> {
+        let cache_policy = self.get_cache_policy()?;
+        if cache_policy.donotcache && !ce.donotcache {
+            let mut updated = (*ce).clone();
+            updated.donotcache = true;
+            return self.enforce_managed(Arc::new(updated));
+        }
+        self.enforce_managed(ce)
+    }
+
+    fn enforce_managed(&self, ce: Arc<ConnectionEntry>) -> Result<Arc<ConnectionEntry>, Error> {
+        let Some(managed) = &self.managed else {
+            return Ok(ce);
+        };
+        let mut ce = (*ce).clone();
+        managed.apply(&mut ce);
+        let errors = managed.check(&ce);
+        if !errors.is_empty() {
+            return Err(Error::msg(format_errors(&errors)));
+        }
+        Ok(Arc::new(ce))
+    }
+
+    /// validate_connection and the checks of the managed policy
+    pub fn validate(&self, ce: &ConnectionEntry) -> Vec<FieldError> {
+        let mut errors = validate_connection(ce);
+        if let Some(managed) = &self.managed {
+            errors.extend(managed.check(ce));
+        }
+        errors
+    }
+
+    pub fn get_managed_policy(&self) -> Option<&ManagedPolicy> {
+        self.managed.as_ref()
     }
 
+    /// Saving a catalog entry stores a local override, only the user specific fields of it are applied.
     pub fn save(&self, mut ce: ConnectionEntry) -> Result<String, Error> {
         if ce.id.is_empty() {
             ce.id = uuid::Uuid::new_v4().to_string();
         }
+        ce.catalog = None;
 
         let mut jh = ce.java_home.trim().to_string();
         if jh.is_empty() {
-            jh = find_java_home();
+            jh = (self.find_java_home)();
         }
         ce.java_home = jh;
+        normalize_entry(&mut ce);
 
-        if let Some(ref username) = ce.username {
-            let username = username.trim();
-            if username.is_empty() {
-                ce.username = None;
-            }
+        if let Some(managed) = &self.managed {
+            managed.apply(&mut ce);
         }
-
-        if let Some(ref password) = ce.password {
-            let password = password.trim();
-            if password.is_empty() {
-                ce.password = None;
-            }
+        let errors = self.validate(&ce);
+        if !errors.is_empty() {
+            return Err(Error::msg(format_errors(&errors)));
         }
 
         let data = serde_json::to_string(&ce)?;
-        self.con_cache
-            .lock()
-            .expect("connection cache lock poisoned")
-            .insert(ce.id.clone(), Arc::new(ce));
-        self.write_connections_to_disk()?;
+        self.modify(|cache| {
+            cache.insert(ce.id.clone(), Arc::new(ce));
+        })?;
         Ok(data)
     }
 
     pub fn delete(&self, id: &str) -> Result<(), Error> {
-        self.con_cache.lock().expect("connection cache lock poisoned").remove(id);
-        self.write_connections_to_disk()?;
-        Ok(())
+        if let Some(entry) = self.get_catalog_entry(id) {
+            return Err(Error::msg(format!(
+                "connection {} is provided by the catalog {} and cannot be deleted",
+                entry.name,
+                entry.catalog.as_deref().unwrap_or_default()
+            )));
+        }
+        self.modify(|cache| {
+            cache.remove(id);
+        })
     }
 
-    pub fn import(&self, file_path: &str, overwrite: bool) -> Result<String, Error> {
-        let f = File::open(file_path)?;
-        let data: Vec<ConnectionEntry> = serde_json::from_reader(f)?;
+    /// Imports the connections from MCAL's data/connections.json, a Ballista export, a CSV file or a directory
+    /// of .jnlp files. Nothing is saved if options.dry_run is set or if there are duplicates without a merge strategy.
+    pub fn import(&self, file_path: &str, options: &ImportOptions) -> Result<String, Error> {
+        let ParsedImport { entries: mut data, warnings } = read_import_source(Path::new(file_path))?;
 
-        // Check for collisions with existing connections
-        let cache = self.con_cache.lock().expect("connection cache lock poisoned");
-        let duplicates: Vec<String> = data
+        // passwords encrypted while exporting
+        let encrypted = data
             .iter()
-            .filter(|ce| cache.contains_key(&ce.id))
-            .map(|ce| ce.name.clone())
-            .collect();
-        drop(cache);
+            .any(|ce| ce.password.as_deref().is_some_and(is_encrypted));
+        if encrypted {
+            let Some(passphrase) = options.passphrase.as_deref() else {
+                let result = serde_json::json!({
+                    "status": "passphrase_required",
+                    "total": data.len(),
+                    "warnings": warnings,
+                });
+                return Ok(result.to_string());
+            };
+            let mut decrypter = SecretDecrypter::new(passphrase);
+            for ce in data.iter_mut() {
+                if let Some(password) = ce.password.as_deref().filter(|p| is_encrypted(p)) {
+                    let password = decrypter.decrypt(password).map_err(|e| {
+                        Error::msg(format!("failed to decrypt the password of {}: {}", ce.name, e))
+                    })?;
+                    ce.password = Some(password);
+                }
+            }
+        }
 
-        if !duplicates.is_empty() && !overwrite {
-            let result = serde_json::json!({
-                "status": "duplicates",
-                "names": duplicates,
-                "total": data.len(),
-            });
-            return Ok(result.to_string());
+        let total = data.len();
+        // the hosts are checked here, the Java homes once they are resolved on this machine
+        let mut rejected = Vec::new();
+        data.retain_mut(|ce| {
+            normalize_entry(ce);
+            let Some(managed) = &self.managed else {
+                return true;
+            };
+            managed.apply(ce);
+            let errors: Vec<FieldError> = managed.check(ce).into_iter().filter(|e| e.field == "address").collect();
+            if !errors.is_empty() {
+                rejected.push(serde_json::json!({"name": ce.name, "errors": errors}));
+            }
+            errors.is_empty()
+        });
+        let local_java_home = (self.find_java_home)();
+        let mut plan = plan_import(&self.merged(), data, options, &local_java_home);
+        plan.invalid.extend(rejected);
+
+        // the same checks as save, most connections share a Java home so each one is only checked once
+        let mut java_homes: HashMap<String, Option<String>> = HashMap::new();
+        let mut invalid = Vec::new();
+        for ce in &plan.changes {
+            let mut errors = Vec::new();
+            let java_home_error = java_homes
+                .entry(ce.java_home.clone())
+                .or_insert_with(|| validate_java_home(&ce.java_home));
+            if let Some(msg) = java_home_error {
+                errors.push(FieldError { field: "javaHome", msg: msg.clone() });
+            }
+            if let Some(managed) = &self.managed {
+                errors.extend(managed.check(ce));
+            }
+            if !errors.is_empty() {
+                invalid.push((ce.id.clone(), errors));
+            }
+        }
+        for (id, errors) in invalid {
+            plan.reject(&id, errors);
         }
 
-        let mut count = 0;
-        let java_home = find_java_home();
-        for mut ce in data {
-            ce.java_home = java_home.clone();
-            self.con_cache
-                .lock()
-                .expect("connection cache lock poisoned")
-                .insert(ce.id.clone(), Arc::new(ce));
-            count += 1;
+        let status = if options.dry_run {
+            "preview"
+        } else if !plan.conflicts.is_empty() {
+            "conflicts"
+        } else {
+            if !plan.changes.is_empty() {
+                self.modify(|cache| {
+                    for ce in &plan.changes {
+                        cache.insert(ce.id.clone(), Arc::new(ce.clone()));
+                    }
+                })?;
+            }
+            "ok"
+        };
+
+        let mut result = serde_json::to_value(&plan)?;
+        result["status"] = serde_json::json!(status);
+        result["total"] = serde_json::json!(total);
+        result["warnings"] = serde_json::json!(warnings);
+        Ok(result.to_string())
+    }
+
+    /// Writes the selected connections as a JSON array in the same format read by import.
+    /// Connections are selected by ids if present, by group otherwise and all are exported if neither is given.
+    pub fn export(&self, ids: Option<&[String]>, group: Option<&str>, file_path: &str, options: &ExportOptions) -> Result<String, Error> {
+        let encrypter = match options.secrets {
+            SecretHandling::Encrypt => {
+                let passphrase = options
+                    .passphrase
+                    .as_deref()
+                    .ok_or_else(|| Error::msg("a passphrase is required to encrypt the passwords"))?;
+                Some(SecretEncrypter::new(passphrase)?)
+            }
+            _ => None,
+        };
+
+        let mut selected: Vec<ConnectionEntry> = {
+            let cache = self.merged();
+            cache
+                .values()
+                .filter(|ce| match (ids, group) {
+                    (Some(ids), _) => ids.contains(&ce.id),
+                    (None, Some(group)) => ce.group == group,
+                    (None, None) => true,
+                })
+                .map(|ce| (**ce).clone())
+                .collect()
+        };
+        selected.sort_by(|a, b| a.group.cmp(&b.group).then_with(|| a.name.cmp(&b.name)));
+
+        for ce in selected.iter_mut() {
+            ce.catalog = None;
+            ce.password = match options.secrets {
+                SecretHandling::Include => ce.password.take(),
+                SecretHandling::Strip => None,
+                SecretHandling::Encrypt => match (&encrypter, ce.password.take()) {
+                    (Some(encrypter), Some(password)) => Some(encrypter.encrypt(&password)?),
+                    _ => None,
+                },
+            };
+
+            if options.exclude_machine_fields {
+                ce.java_home = String::new();
+                ce.working_dir = None;
+                ce.last_connected = None;
+            }
         }
 
-        self.write_connections_to_disk()?;
+        let val = serde_json::to_string_pretty(&selected)?;
+        write_atomic(Path::new(file_path), val.as_bytes(), 0)?;
         let result = serde_json::json!({
             "status": "ok",
-            "total": count,
+            "total": selected.len(),
         });
         Ok(result.to_string())
     }
 
     pub fn add_trusted_cert(&self, cert_der: &str) -> Result<(), Error> {
         let mut certs = parse_trusted_certs(&self.trusted_certs_location);
-        let mut hasher = Sha256::new();
-        hasher.update(cert_der);
-        let hash = hasher.finalize();
-        let hash = hex::encode(&hash);
-
         let cert_der = openssl::base64::decode_block(cert_der)?;
         let cert = X509::from_der(cert_der.as_slice())?;
+        let hash = cert_fingerprint(&cert)?;
         if let None = certs.get(&hash) {
             certs.insert(hash, cert);
         }
@@ -247,13 +677,7 @@ impl ConnectionStore {
             der_certs.insert(key.to_string(), der);
         }
         let val = serde_json::to_string_pretty(&der_certs)?;
-        let mut f = OpenOptions::new()
-            .append(false)
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.trusted_certs_location)?;
-        f.write_all(val.as_bytes())?;
+        write_atomic(&self.trusted_certs_location, val.as_bytes(), BACKUP_COUNT)?;
 
         let new_store = create_cert_store(certs);
         *self.cert_store.lock().expect("cert store lock poisoned") = Arc::new(new_store);
@@ -265,49 +689,209 @@ impl ConnectionStore {
         t.clone()
     }
 
+    /// read on every launch so that changes to the file apply without a restart
+    /// The JVM argument policy of the managed policy, otherwise the one of the profile. The latter can't allow
+    /// the arguments denied by default while a managed policy is installed.
+    pub fn get_arg_policy(&self) -> Result<ArgPolicy, Error> {
+        let Some(managed) = &self.managed else {
+            return ArgPolicy::load(&self.jvm_policy_location);
+        };
+        match &managed.jvm_args {
+            Some(jvm_args) => Ok(jvm_args.clone()),
+            None => Ok(ArgPolicy::load(&self.jvm_policy_location)?.without_default_deny_overrides()),
+        }
+    }
+
+    pub fn get_verify_policy(&self) -> Result<VerifyPolicy, Error> {
+        resolve_verify_policy(self.managed.as_ref(), &self.verify_policy_location, &self.crl_cache_dir)
+    }
+
+    /// read on every use, like the other policies
+    pub fn get_cache_policy(&self) -> Result<CachePolicy, Error> {
+        CachePolicy::load(&self.cache_policy_location)
+    }
+
+    /// the jars that passed the verification with the current trust store, see VerifyCache
+    pub fn get_verify_cache_location(&self) -> PathBuf {
+        self.verify_cache_location.clone()
+    }
+
     pub fn get_trusted_certs(&self) -> Vec<X509> {
         let certs = parse_trusted_certs(&self.trusted_certs_location);
         certs.into_values().collect()
     }
 
-    fn write_connections_to_disk(&self) -> Result<(), Error> {
-        let c = self.con_cache.lock().expect("connection cache lock poisoned");
-        let val = serde_json::to_string_pretty(c.deref())?;
-        let mut f = OpenOptions::new()
-            .append(false)
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.con_location)
-            .map_err(|e| {
-                println!("unable to open file for writing: {}", e);
-                Error::new(e)
-            })?;
-        f.write_all(val.as_bytes())?;
+    /// Applies f to the connections and writes them to disk. Changes made to the data file by someone else
+    /// are loaded first so that they are not clobbered. Nothing is written while the file can't be parsed,
+    /// e.g. while it is half edited, or while it conflicts with in-memory changes that could not be written
+    /// earlier, the latter has to be settled with resolve_conflict.
+    fn modify<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut HashMap<String, Arc<ConnectionEntry>>),
+    {
+        let mut cache = self.con_cache.lock().expect("connection cache lock poisoned");
+        match self.reload_locked(&mut cache)? {
+            ReloadOutcome::Conflict => {
+                return Err(Error::msg(format!(
+                    "{:?} was changed by another program while there are unsaved changes, resolve the conflict first",
+                    self.con_location
+                )));
+            }
+            ReloadOutcome::Invalid(e) => {
+                return Err(Error::msg(format!(
+                    "{:?} was changed by another program and is not valid, fix it before saving: {}",
+                    self.con_location, e
+                )));
+            }
+            ReloadOutcome::Unchanged | ReloadOutcome::Reloaded => {}
+        }
+        f(&mut cache);
+        self.write_locked(&cache)
+    }
+
+    fn write_locked(&self, cache: &HashMap<String, Arc<ConnectionEntry>>) -> Result<(), Error> {
+        let val = serde_json::to_string_pretty(cache)?;
+        let r = write_atomic(&self.con_location, val.as_bytes(), BACKUP_COUNT);
+        if let Err(e) = r {
+            println!("unable to write connections to {:?}: {}", self.con_location, e);
+            self.unsaved.store(true, Ordering::SeqCst);
+            return Err(e);
+        }
+        self.unsaved.store(false, Ordering::SeqCst);
+        *self.last_synced.lock().expect("last synced lock poisoned") = Some(sha256_hex(val.as_bytes()));
         Ok(())
     }
 
-    pub fn update_last_connected(&self, id: &str) -> Result<(), Error> {
+    /// Reloads the connections if the data file was changed by another program, e.g. edited by the user
+    /// or synced from another machine. Meant to be polled by a watcher.
+    pub fn reload_if_changed(&self) -> Result<ReloadOutcome, Error> {
         let mut cache = self.con_cache.lock().expect("connection cache lock poisoned");
-        if let Some(entry) = cache.get(id) {
-            let mut updated = (**entry).clone();
-            updated.last_connected = Some(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .expect("system clock is before UNIX epoch")
-                    .as_millis() as i64,
-            );
-            cache.insert(id.to_string(), Arc::new(updated));
+        self.reload_locked(&mut cache)
+    }
+
+    /// Resolves a conflict reported by reload_if_changed, either by discarding the
+    /// unsaved in-memory changes and loading the file or by writing them over the file.
+    pub fn resolve_conflict(&self, use_disk: bool) -> Result<(), Error> {
+        let mut cache = self.con_cache.lock().expect("connection cache lock poisoned");
+        if use_disk {
+            self.unsaved.store(false, Ordering::SeqCst);
+            *self.last_synced.lock().expect("last synced lock poisoned") = None;
+            match self.reload_locked(&mut cache)? {
+                ReloadOutcome::Invalid(e) => Err(Error::msg(format!("failed to parse {:?}: {}", self.con_location, e))),
+                _ => Ok(()),
+            }
+        } else {
+            self.write_locked(&cache)
+        }
+    }
+
+    fn reload_locked(&self, cache: &mut HashMap<String, Arc<ConnectionEntry>>) -> Result<ReloadOutcome, Error> {
+        let data = match fs::read(&self.con_location) {
+            Ok(data) => data,
+            // it will be created again on the next write
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ReloadOutcome::Unchanged),
+            Err(e) => return Err(Error::new(e)),
+        };
+
+        let hash = sha256_hex(&data);
+        let mut last_synced = self.last_synced.lock().expect("last synced lock poisoned");
+        if last_synced.as_deref() == Some(hash.as_str()) {
+            return Ok(ReloadOutcome::Unchanged);
         }
-        drop(cache);
-        self.write_connections_to_disk()?;
+        if self.unsaved.load(Ordering::SeqCst) {
+            return Ok(ReloadOutcome::Conflict);
+        }
+
+        // could be in the middle of being written by an editor, tried again on the next poll
+        let parsed: HashMap<String, ConnectionEntry> = match serde_json::from_slice(&data) {
+            Ok(parsed) => parsed,
+            Err(e) => return Ok(ReloadOutcome::Invalid(e.to_string())),
+        };
+        println!("reloading connections from {:?}", self.con_location);
+        *cache = parsed.into_iter().map(|(id, ce)| (id, Arc::new(ce))).collect();
+        *last_synced = Some(hash);
+        Ok(ReloadOutcome::Reloaded)
+    }
+
+    pub fn update_last_connected(&self, id: &str) -> Result<(), Error> {
+        // for a catalog entry without an override this creates one
+        let catalog_entry = self.get_catalog_entry(id);
+        self.modify(|cache| {
+            let entry = cache.get(id).cloned().or(catalog_entry);
+            if let Some(entry) = entry {
+                let mut updated = (*entry).clone();
+                updated.catalog = None;
+                updated.last_connected = Some(
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .expect("system clock is before UNIX epoch")
+                        .as_millis() as i64,
+                );
+                cache.insert(id.to_string(), Arc::new(updated));
+            }
+        })
+    }
+
+    pub fn get_catalogs(&self) -> serde_json::Value {
+        let catalogs = self.catalogs.lock().expect("catalogs lock poisoned");
+        let mut arr = Vec::with_capacity(catalogs.len());
+        for c in catalogs.iter() {
+            let mut v = serde_json::json!(c);
+            v["total"] = serde_json::json!(c.entries.len());
+            arr.push(v);
+        }
+        serde_json::Value::Array(arr)
+    }
+
+    pub fn add_catalog(&self, source: CatalogSource) -> Result<(), Error> {
+        let name = source.name.trim();
+        if name.is_empty() || source.location.trim().is_empty() {
+            return Err(Error::msg("catalog name and location are required"));
+        }
+        let mut sources = read_catalog_sources(&self.catalog_sources_location)?;
+        if sources.iter().any(|s| s.name == name) {
+            return Err(Error::msg(format!("a catalog with the name {} already exists", name)));
+        }
+
+        let source = CatalogSource {
+            name: name.to_string(),
+            location: source.location.trim().to_string(),
+        };
+        let catalog = load_catalog(&source, &self.catalog_cache_dir, true);
+        if let Some(ref e) = catalog.error {
+            if catalog.entries.is_empty() {
+                return Err(Error::msg(format!("failed to load the catalog {}: {}", name, e)));
+            }
+        }
+        sources.push(source);
+        write_catalog_sources(&self.catalog_sources_location, &sources)?;
+        self.catalogs.lock().expect("catalogs lock poisoned").push(catalog);
         Ok(())
     }
 
-    pub fn get_all_groups(&self) -> Result<HashSet<String>, Error> {
-        let connections = self.con_cache
+    /// the local overrides of the removed catalog's entries become regular connections
+    pub fn remove_catalog(&self, name: &str) -> Result<(), Error> {
+        let mut sources = read_catalog_sources(&self.catalog_sources_location)?;
+        sources.retain(|s| s.name != name);
+        write_catalog_sources(&self.catalog_sources_location, &sources)?;
+        self.catalogs
             .lock()
-            .expect("connection cache lock poisoned");
+            .expect("catalogs lock poisoned")
+            .retain(|c| c.source.name != name);
+        Ok(())
+    }
+
+    pub fn reload_catalogs(&self) -> Result<(), Error> {
+        let catalogs: Vec<Catalog> = read_catalog_sources(&self.catalog_sources_location)?
+            .iter()
+            .map(|source| load_catalog(source, &self.catalog_cache_dir, true))
+            .collect();
+        *self.catalogs.lock().expect("catalogs lock poisoned") = catalogs;
+        Ok(())
+    }
+
+    pub fn get_all_groups(&self) -> Result<HashSet<String>, Error> {
+        let connections = self.merged();
 
         let mut groups: HashSet<String> = HashSet::new();
 
@@ -357,7 +941,93 @@ pub fn find_java_home() -> String {
     java_home
 }
 
-fn parse_trusted_certs(trusted_certs_location: &PathBuf) -> FxHashMap<String, X509> {
+/// Clears the blank optional fields, applied to the saved and the imported connections alike.
+fn normalize_entry(ce: &mut ConnectionEntry) {
+    if let Some(ref username) = ce.username {
+        let username = username.trim();
+        if username.is_empty() {
+            ce.username = None;
+        }
+    }
+
+    if let Some(ref password) = ce.password {
+        let password = password.trim();
+        if password.is_empty() {
+            ce.password = None;
+        }
+    }
+
+    if let Some(ref working_dir) = ce.working_dir {
+        let working_dir = working_dir.trim();
+        if working_dir.is_empty() {
+            ce.working_dir = None;
+        } else {
+            ce.working_dir = Some(working_dir.to_string());
+        }
+    }
+
+    ce.env_vars.retain(|k, _| !k.trim().is_empty());
+    ce.system_properties.retain(|k, _| !k.trim().is_empty());
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// hex encoded SHA-256 digest of the DER encoded certificate, the trusted certs are keyed by it
+pub fn cert_fingerprint(cert: &X509) -> Result<String, Error> {
+    let digest = cert.digest(MessageDigest::sha256())?;
+    Ok(hex::encode(digest))
+}
+
+fn temporary_id(address: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"temporary\n");
+    hasher.update(address.as_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Uuid::from_bytes(bytes).to_string()
+}
+
+fn resolve_verify_policy(
+    managed: Option<&ManagedPolicy>,
+    verify_policy_location: &Path,
+    crl_cache_dir: &Path,
+) -> Result<VerifyPolicy, Error> {
+    let mut policy = match managed.map(|m| m.verification.as_ref()) {
+        Some(Some(verification)) => verification.clone(),
+        // the profile's policy can be edited by the user, it may only be stricter than the default one
+        Some(None) => {
+            let mut policy = VerifyPolicy::load(verify_policy_location)?;
+            if policy.allow_weak_digests {
+                println!("ignoring allow_weak_digests of {:?} under the managed policy", verify_policy_location);
+                policy.allow_weak_digests = false;
+            }
+            policy
+        }
+        None => VerifyPolicy::load(verify_policy_location)?,
+    };
+    policy.crl_cache_dir = Some(crl_cache_dir.to_path_buf());
+    Ok(policy)
+}
+
+/// The verification policy and the trusted certificates of a profile read straight from its files,
+/// for verifying jars without opening, and migrating, the whole store.
+pub fn read_verify_settings(data_dir_path: &Path) -> Result<(VerifyPolicy, FxHashMap<String, X509>), Error> {
+    let managed = ManagedPolicy::load(&system_policy_path())?;
+    let policy = resolve_verify_policy(
+        managed.as_ref(),
+        &data_dir_path.join("ballista-verify-policy.json"),
+        &data_dir_path.join("cache").join("crls"),
+    )?;
+    let certs = parse_trusted_certs(&data_dir_path.join("ballista-trusted-certs.json"));
+    Ok((policy, certs))
+}
+
+pub(crate) fn parse_trusted_certs(trusted_certs_location: &Path) -> FxHashMap<String, X509> {
     let mut certs = FxHashMap::default();
     let trusted_certs_location_file = File::open(trusted_certs_location);
     if let Ok(trusted_certs_location_file) = trusted_certs_location_file {
@@ -398,7 +1068,7 @@ fn parse_trusted_certs(trusted_certs_location: &PathBuf) -> FxHashMap<String, X5
     certs
 }
 
-fn create_cert_store(certs: FxHashMap<String, X509>) -> X509Store {
+pub(crate) fn create_cert_store(certs: FxHashMap<String, X509>) -> X509Store {
     if !openssl_probe::has_ssl_cert_env_vars() {
         println!("probing and setting OpenSSL environment variables");
         // SAFETY: must be called before any OpenSSL operations to set cert paths
@@ -434,3 +1104,356 @@ fn get_default_notes() -> String {
 fn get_default_donotcache() -> bool {
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_store() -> (PathBuf, ConnectionStore) {
+        create_store_with_policy(None)
+    }
+
+    /// independent of the managed policy and the Java of the machine running the tests,
+    /// the connections saved without a Java home keep it empty and no java is run
+    fn create_store_with_policy(managed: Option<ManagedPolicy>) -> (PathBuf, ConnectionStore) {
+        let dir = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut cs = ConnectionStore::init_with_policy(dir.clone(), managed).unwrap();
+        cs.find_java_home = String::new;
+        (dir, cs)
+    }
+
+    fn create_entry(name: &str, group: &str) -> ConnectionEntry {
+        ConnectionEntry {
+            name: name.to_string(),
+            address: String::from("https://localhost:8443"),
+            group: group.to_string(),
+            java_home: String::new(),
+            username: Some(String::from("admin")),
+            password: Some(String::from("admin123")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_and_import_with_encrypted_passwords() {
+        let (src_dir, src) = create_store();
+        src.save(create_entry("prod-1", "Production")).unwrap();
+        src.save(create_entry("prod-2", "Production")).unwrap();
+        src.save(create_entry("test-1", "Test")).unwrap();
+
+        let export_file = src_dir.join("export.json");
+        let export_file = export_file.to_str().unwrap();
+        let options = ExportOptions {
+            secrets: SecretHandling::Encrypt,
+            passphrase: Some(String::from("s3cret")),
+            exclude_machine_fields: true,
+        };
+        src.export(None, Some("Production"), export_file, &options).unwrap();
+
+        let exported: Vec<ConnectionEntry> = serde_json::from_reader(File::open(export_file).unwrap()).unwrap();
+        assert_eq!(2, exported.len());
+        assert!(exported.iter().all(|ce| is_encrypted(ce.password.as_deref().unwrap())));
+
+        let (dst_dir, dst) = create_store();
+        let mut options = ImportOptions::default();
+        let r = dst.import(export_file, &options).unwrap();
+        assert!(r.contains("passphrase_required"));
+        options.passphrase = Some(String::from("wrong"));
+        assert!(dst.import(export_file, &options).is_err());
+
+        options.passphrase = Some(String::from("s3cret"));
+        dst.import(export_file, &options).unwrap();
+        for ce in exported {
+            let imported = dst.get(&ce.id).unwrap();
+            assert_eq!(Some("admin123"), imported.password.as_deref());
+        }
+
+        fs::remove_dir_all(src_dir).unwrap();
+        fs::remove_dir_all(dst_dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_checks_resolved_java_homes() {
+        let managed = ManagedPolicy {
+            allowed_java_homes: vec![String::from("/opt/allowed/*")],
+            ..Default::default()
+        };
+        let (dir, mut cs) = create_store_with_policy(Some(managed));
+        cs.find_java_home = || String::from("/opt/other/jdk-17");
+
+        let mut ce = create_entry("imported", "Default");
+        ce.username = Some(String::from("  "));
+        ce.java_home = String::from("/opt/allowed/jdk-17");
+        let import_file = dir.join("import.json");
+        fs::write(&import_file, serde_json::to_string(&vec![ce]).unwrap()).unwrap();
+
+        // the Java home is not kept, the local one is not allowed by the policy
+        let r = cs.import(import_file.to_str().unwrap(), &ImportOptions::default()).unwrap();
+        let r: serde_json::Value = serde_json::from_str(&r).unwrap();
+        assert_eq!(0, r["added"].as_array().unwrap().len());
+        assert_eq!("imported", r["invalid"][0]["name"]);
+        assert!(cs.list().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_strips_passwords() {
+        let (dir, cs) = create_store();
+        let ce = create_entry("prod-1", "Production");
+        let id = ce.id.clone();
+        cs.save(ce).unwrap();
+
+        let export_file = dir.join("export.json");
+        let export_file = export_file.to_str().unwrap();
+        let options = ExportOptions {
+            secrets: SecretHandling::Strip,
+            passphrase: None,
+            exclude_machine_fields: false,
+        };
+        cs.export(Some(&[id]), None, export_file, &options).unwrap();
+
+        let exported: Vec<ConnectionEntry> = serde_json::from_reader(File::open(export_file).unwrap()).unwrap();
+        assert_eq!(1, exported.len());
+        assert_eq!(None, exported[0].password);
+        assert_eq!(Some("admin"), exported[0].username.as_deref());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_entries_are_merged_and_read_only() {
+        let (dir, cs) = create_store();
+        let location = fs::canonicalize("test-resources/catalog/ops-engines.json").unwrap();
+        cs.add_catalog(CatalogSource {
+            name: String::from("ops"),
+            location: location.to_str().unwrap().to_string(),
+        })
+        .unwrap();
+        cs.save(create_entry("mine", "Default")).unwrap();
+
+        let all: Vec<ConnectionEntry> = serde_json::from_str(&cs.to_json_array_string()).unwrap();
+        assert_eq!(3, all.len());
+
+        let id = cs.find("Prod East").unwrap().id.clone();
+        let id = id.as_str();
+        assert!(cs.delete(id).is_err());
+
+        // only the user specific fields of the override are applied
+        let mut ce = (*cs.get(id).unwrap()).clone();
+        ce.username = Some(String::from("me"));
+        ce.heap_size = String::from("2g");
+        ce.address = String::from("https://elsewhere.example.com");
+        cs.save(ce).unwrap();
+
+        let merged = cs.get(id).unwrap();
+        assert_eq!(Some("ops"), merged.catalog.as_deref());
+        assert_eq!(Some("me"), merged.username.as_deref());
+        assert_eq!("2g", merged.heap_size);
+        assert_eq!("https://prod-east.example.com:8443", merged.address);
+
+        // the catalog entries are not written to the data file
+        let stored: HashMap<String, ConnectionEntry> =
+            serde_json::from_reader(File::open(dir.join("ballista-data.json")).unwrap()).unwrap();
+        assert_eq!(2, stored.len());
+        assert!(stored.values().all(|ce| ce.catalog.is_none()));
+
+        cs.remove_catalog("ops").unwrap();
+        assert_eq!("https://elsewhere.example.com", cs.get(id).unwrap().address);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_external_changes_are_reloaded() {
+        let (dir, cs) = create_store();
+        let mine = create_entry("mine", "Default");
+        cs.save(mine.clone()).unwrap();
+        assert_eq!(ReloadOutcome::Unchanged, cs.reload_if_changed().unwrap());
+
+        // another program adds an entry
+        let data_file = dir.join("ballista-data.json");
+        let mut stored: HashMap<String, ConnectionEntry> =
+            serde_json::from_reader(File::open(&data_file).unwrap()).unwrap();
+        let theirs = create_entry("theirs", "Default");
+        stored.insert(theirs.id.clone(), theirs.clone());
+        fs::write(&data_file, serde_json::to_string(&stored).unwrap()).unwrap();
+
+        // the external change is not clobbered by the next save
+        cs.save(create_entry("another", "Default")).unwrap();
+        assert!(cs.get(&theirs.id).is_some());
+        assert!(cs.get(&mine.id).is_some());
+        let stored: HashMap<String, ConnectionEntry> =
+            serde_json::from_reader(File::open(&data_file).unwrap()).unwrap();
+        assert_eq!(3, stored.len());
+
+        // a half written file is ignored until it becomes valid and is never overwritten
+        fs::write(&data_file, "{\"broken").unwrap();
+        assert!(matches!(cs.reload_if_changed().unwrap(), ReloadOutcome::Invalid(_)));
+        assert!(cs.get(&theirs.id).is_some());
+        assert!(cs.save(create_entry("rejected", "Default")).is_err());
+        assert_eq!("{\"broken", fs::read_to_string(&data_file).unwrap());
+        fs::write(&data_file, "{}").unwrap();
+        assert_eq!(ReloadOutcome::Reloaded, cs.reload_if_changed().unwrap());
+        assert!(cs.get(&mine.id).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_and_prune_cache() {
+        let (dir, cs) = create_store();
+        let ce = create_entry("Prod-1", "Production");
+        cs.save(ce.clone()).unwrap();
+        let mut dup = create_entry("dup", "Test");
+        cs.save(dup.clone()).unwrap();
+        dup.id = Uuid::new_v4().to_string();
+        cs.save(dup).unwrap();
+
+        assert_eq!(ce.id, cs.find("prod-1").unwrap().id);
+        assert_eq!(ce.id, cs.find(&ce.id).unwrap().id);
+        assert!(cs.find("dup").is_err());
+        assert!(cs.find("missing").is_err());
+
+        let owned = connection_cache_dir(&cs.cache_dir, &ce.id);
+        let orphan = cs.cache_dir.join("0badc0de-0000-4000-8000-000000000000");
+        fs::create_dir_all(&owned).unwrap();
+        fs::create_dir_all(&orphan).unwrap();
+        assert_eq!(vec![orphan.clone()], cs.prune_cache().unwrap());
+        assert!(owned.exists());
+        assert!(!orphan.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_policy() {
+        let (dir, cs) = create_store();
+        let mut recent = create_entry("recent", "Default");
+        recent.last_connected = Some(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+        );
+        let mut stale = create_entry("stale", "Default");
+        stale.last_connected = Some(0);
+        cs.save(recent.clone()).unwrap();
+        cs.save(stale.clone()).unwrap();
+        assert!(!cs.enforce(Arc::new(recent.clone())).unwrap().donotcache);
+
+        fs::write(dir.join("ballista-cache-policy.json"), r#"{"donotcache": true, "max_unused_days": 30}"#).unwrap();
+        assert!(cs.enforce(Arc::new(recent.clone())).unwrap().donotcache);
+
+        let recent_dir = connection_cache_dir(&cs.cache_dir, &recent.id);
+        let stale_dir = connection_cache_dir(&cs.cache_dir, &stale.id);
+        fs::create_dir_all(&recent_dir).unwrap();
+        fs::create_dir_all(&stale_dir).unwrap();
+        assert_eq!(vec![stale_dir], cs.prune_cache().unwrap());
+        assert!(recent_dir.exists());
+
+        fs::write(dir.join("ballista-cache-policy.json"), r#"{"keep": "forever"}"#).unwrap();
+        assert!(cs.get_cache_policy().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_temporary_connections_are_not_saved() {
+        let (dir, cs) = create_store();
+        let saved = create_entry("saved", "Default");
+        cs.save(saved.clone()).unwrap();
+
+        assert_eq!(saved.id, cs.add_temporary("https://localhost:8443/").unwrap().id);
+        let temp = cs.add_temporary("https://adhoc.example.com:8443").unwrap();
+        assert_eq!("adhoc.example.com", temp.name);
+        assert!(cs.get(&temp.id).is_some());
+        assert_eq!(temp.id, cs.add_temporary("https://adhoc.example.com:8443/").unwrap().id);
+        assert_eq!(1, cs.list().len());
+
+        let jnlp = cs.add_temporary("test-resources/import/jnlp/engine-a.jnlp").unwrap();
+        assert_eq!("engine-a", jnlp.name);
+        assert!(Path::new(&jnlp.address).is_absolute());
+        let jnlp_again = cs.add_temporary("./test-resources/import/jnlp/engine-a.jnlp").unwrap();
+        assert_eq!(jnlp.id, jnlp_again.id);
+
+        // the query string of a JNLP URL is part of the address
+        let tenant_a = cs.add_temporary("https://proxy/x/launch.jnlp?tenant=a").unwrap();
+        assert_eq!("https://proxy/x/launch.jnlp?tenant=a", tenant_a.address);
+        assert_eq!("proxy", tenant_a.name);
+        let tenant_b = cs.add_temporary("https://proxy/x/launch.jnlp?tenant=b").unwrap();
+        assert_ne!(tenant_a.id, tenant_b.id);
+        assert!(cs.add_temporary("https://:8443/launch.jnlp").is_err());
+
+        cs.update_last_connected(&temp.id).unwrap();
+        let stored: HashMap<String, ConnectionEntry> =
+            serde_json::from_reader(File::open(dir.join("ballista-data.json")).unwrap()).unwrap();
+        assert_eq!(1, stored.len());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_managed_policy_is_enforced() {
+        let managed = ManagedPolicy {
+            force_verify: true,
+            disallow_passwords: true,
+            allowed_hosts: vec![String::from("localhost")],
+            ..Default::default()
+        };
+        let (dir, cs) = create_store_with_policy(Some(managed));
+
+        let mut ce = create_entry("locked", "Default");
+        ce.verify = false;
+        let id = cs.save(ce).unwrap();
+        let id = serde_json::from_str::<ConnectionEntry>(&id).unwrap().id;
+        let saved = cs.get(&id).unwrap();
+        assert!(saved.verify);
+        assert_eq!(None, saved.password);
+
+        let mut elsewhere = create_entry("elsewhere", "Default");
+        elsewhere.address = String::from("https://evil.example.com:8443");
+        assert!(cs.save(elsewhere.clone()).is_err());
+        assert!(cs.add_temporary("https://evil.example.com:8443").is_err());
+        assert!(cs.add_temporary("test-resources/import/jnlp/engine-a.jnlp").is_err());
+
+        // entries stored before the policy was installed are fixed or rejected on launch
+        elsewhere.id = Uuid::new_v4().to_string();
+        assert!(cs.enforce(Arc::new(elsewhere)).is_err());
+        let mut old = create_entry("old", "Default");
+        old.verify = false;
+        assert!(cs.enforce(Arc::new(old)).unwrap().verify);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_profile_policies_cannot_relax_a_managed_policy() {
+        use crate::argpolicy::ArgSource;
+        use crate::verify::RevocationCheck;
+
+        let write_policies = |dir: &Path| {
+            fs::write(dir.join("ballista-jvm-policy.json"), r#"{"allow": ["-javaagent:*", "*", "-Dacme.*"]}"#).unwrap();
+            fs::write(
+                dir.join("ballista-verify-policy.json"),
+                r#"{"allow_weak_digests": true, "revocation": "hard_fail"}"#,
+            )
+            .unwrap();
+        };
+
+        // without a managed policy the profile's policies are used as they are
+        let (dir, cs) = create_store();
+        write_policies(&dir);
+        assert_eq!(None, cs.get_arg_policy().unwrap().check("-javaagent:/tmp/agent.jar", ArgSource::Connection));
+        assert!(cs.get_verify_policy().unwrap().allow_weak_digests);
+        fs::remove_dir_all(dir).unwrap();
+
+        // a managed policy without jvm_args and verification
+        let (dir, cs) = create_store_with_policy(Some(ManagedPolicy {
+            force_verify: true,
+            ..Default::default()
+        }));
+        write_policies(&dir);
+        let policy = cs.get_arg_policy().unwrap();
+        assert!(policy.check("-javaagent:/tmp/agent.jar", ArgSource::Connection).is_some());
+        assert!(policy.check("-agentpath:/tmp/libagent.so", ArgSource::Connection).is_some());
+        assert_eq!(None, policy.check("-Dacme.theme=dark", ArgSource::Connection));
+        let policy = cs.get_verify_policy().unwrap();
+        assert!(!policy.allow_weak_digests);
+        assert_eq!(RevocationCheck::HardFail, policy.revocation);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
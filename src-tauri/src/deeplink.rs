@@ -0,0 +1,178 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::validate::validate_address;
+use crate::webstart::{is_jnlp_location, normalize_url};
+
+pub const SCHEME: &str = "ballista";
+
+/// ballista://launch/<id-or-name>, ballista://open?address=https://host:8443 or a JNLP file that was opened
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DeepLink {
+    /// a saved connection
+    Launch { connection: String },
+    /// an address or a JNLP file that is not necessarily saved, the user must confirm before it is launched
+    Open { address: String },
+}
+
+/// The link the launcher was started with, or received while running, until the UI picks it up.
+#[derive(Default)]
+pub struct PendingDeepLink(Mutex<Option<DeepLink>>);
+
+impl PendingDeepLink {
+    pub fn set(&self, link: DeepLink) {
+        *self.0.lock().expect("deep link lock poisoned") = Some(link);
+    }
+
+    pub fn take(&self) -> Option<DeepLink> {
+        self.0.lock().expect("deep link lock poisoned").take()
+    }
+}
+
+pub fn parse(link: &str) -> Result<DeepLink, Error> {
+    let url = Url::parse(link).map_err(|e| Error::msg(format!("invalid link {}: {}", link, e)))?;
+    if url.scheme() != SCHEME {
+        return Err(Error::msg(format!("not a {}:// link: {}", SCHEME, link)));
+    }
+
+    // the action is the host part, e.g. launch in ballista://launch/engine-a
+    match url.host_str() {
+        Some("launch") => {
+            let connection = url
+                .path_segments()
+                .and_then(|mut s| s.find(|s| !s.is_empty()))
+                .ok_or_else(|| Error::msg(format!("missing connection id or name in {}", link)))?;
+            let connection = percent_decode(connection)?;
+            Ok(DeepLink::Launch { connection })
+        }
+        Some("open") => {
+            let address = url
+                .query_pairs()
+                .find(|(k, _)| k == "address")
+                .map(|(_, v)| v.to_string())
+                .ok_or_else(|| Error::msg(format!("missing address in {}", link)))?;
+            if let Some(e) = validate_address(&address) {
+                return Err(Error::msg(e));
+            }
+            let (address, _host) = normalize_url(&address)?;
+            Ok(DeepLink::Open { address })
+        }
+        _ => Err(Error::msg(format!("unsupported link {}", link))),
+    }
+}
+
+/// Parses a ballista:// link or the location of a JNLP file the launcher was asked to open.
+pub fn parse_opened(location: &str) -> Result<DeepLink, Error> {
+    if is_link(location) {
+        return parse(location);
+    }
+    if is_jnlp_location(location) {
+        return Ok(DeepLink::Open {
+            address: location.to_string(),
+        });
+    }
+    Err(Error::msg(format!("unsupported link {}", location)))
+}
+
+/// Finds the first ballista:// link or JNLP file in the command line args, this is how Linux and Windows pass them.
+pub fn find_in_args(args: &[String]) -> Option<&str> {
+    args.iter()
+        .map(String::as_str)
+        .find(|a| is_link(a) || is_jnlp_location(a))
+}
+
+/// Resolves a relative JNLP path passed to another instance against the directory it was started in,
+/// links and URLs are returned as they are.
+pub fn resolve_in(location: &str, cwd: &Path) -> String {
+    let is_url = Url::parse(location).is_ok_and(|u| matches!(u.scheme(), "http" | "https" | "file"));
+    let path = Path::new(location);
+    if is_link(location) || is_url || path.is_absolute() {
+        return location.to_string();
+    }
+    cwd.join(path).to_string_lossy().to_string()
+}
+
+fn is_link(s: &str) -> bool {
+    s.to_ascii_lowercase().starts_with(&format!("{}://", SCHEME))
+}
+
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let b = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| Error::msg(format!("invalid escape in {}", s)))?;
+            decoded.push(b);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            DeepLink::Launch { connection: String::from("engine-a") },
+            parse("ballista://launch/engine-a").unwrap()
+        );
+        assert_eq!(
+            DeepLink::Launch { connection: String::from("Prod East") },
+            parse("ballista://launch/Prod%20East").unwrap()
+        );
+        assert_eq!(
+            DeepLink::Open { address: String::from("https://host:8443") },
+            parse("ballista://open?address=https%3A%2F%2Fhost%3A8443%2F").unwrap()
+        );
+
+        assert!(parse("ballista://launch/").is_err());
+        assert!(parse("ballista://open").is_err());
+        assert!(parse("ballista://open?address=ftp://host").is_err());
+        assert!(parse("ballista://delete/engine-a").is_err());
+        assert!(parse("https://launch/engine-a").is_err());
+    }
+
+    #[test]
+    fn test_find_in_args() {
+        let args = vec![String::from("--profile=prod"), String::from("ballista://launch/x")];
+        assert_eq!(Some("ballista://launch/x"), find_in_args(&args));
+        assert_eq!(None, find_in_args(&args[..1]));
+
+        let args = vec![String::from("/tmp/engine.jnlp")];
+        assert_eq!(Some("/tmp/engine.jnlp"), find_in_args(&args));
+        assert_eq!(
+            DeepLink::Open { address: String::from("/tmp/engine.jnlp") },
+            parse_opened("/tmp/engine.jnlp").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_in() {
+        let cwd = Path::new("/home/user/Downloads");
+        assert_eq!("ballista://launch/x", resolve_in("ballista://launch/x", cwd));
+        assert_eq!("https://host/webstart.jnlp", resolve_in("https://host/webstart.jnlp", cwd));
+        assert_eq!(cwd.join("engine.jnlp").to_string_lossy(), resolve_in("engine.jnlp", cwd));
+        #[cfg(unix)]
+        assert_eq!("/tmp/engine.jnlp", resolve_in("/tmp/engine.jnlp", cwd));
+    }
+}
@@ -5,6 +5,7 @@
 use openssl::error::ErrorStack;
 use openssl::x509::{X509NameRef, X509};
 use rustc_hash::FxHashMap;
+use serde::Serialize;
 use serde_json::{Number, Value};
 use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
@@ -15,6 +16,8 @@ use zip::result::ZipError;
 #[derive(Debug)]
 pub struct VerificationError {
     pub(crate) cert: Option<X509>,
+    /// the signer's certificate followed by the other certificates of the signature block, from its issuer up
+    pub(crate) chain: Vec<X509>,
     pub(crate) msg: String,
 }
 
@@ -24,36 +27,40 @@ impl VerificationError {
         obj.insert("msg", Value::String(self.msg.clone()));
         obj.insert("code", Value::Number(Number::from(1)));
         if let Some(ref cert) = self.cert {
-            let mut cert_details = serde_json::Map::new();
-            let der = match cert.to_der() {
-                Ok(d) => d,
-                Err(_) => return format!("{{\"msg\":\"{}\",\"code\":1}}", self.msg),
+            let Some(cert_details) = cert_details(cert) else {
+                return format!("{{\"msg\":\"{}\",\"code\":1}}", self.msg);
             };
-            let der = openssl::base64::encode_block(der.as_slice());
-            cert_details.insert("der".to_string(), Value::String(der));
-            let subject = format_name(cert.subject_name());
-            cert_details.insert("subject".to_string(), Value::String(subject));
-
-            let issuer = format_name(cert.issuer_name());
-            cert_details.insert("issuer".to_string(), Value::String(issuer));
-
-            let expires_on = cert.not_after().to_string();
-            cert_details.insert("expires_on".to_string(), Value::String(expires_on));
-
-            let sha256_sum_bytes = match cert.digest(MessageDigest::sha256()) {
-                Ok(d) => d,
-                Err(_) => return format!("{{\"msg\":\"{}\",\"code\":1}}", self.msg),
-            };
-            let sha256_string = hex::encode(sha256_sum_bytes);
-            cert_details.insert("sha256sum".to_string(), Value::String(sha256_string));
-
             obj.insert("cert", Value::Object(cert_details));
         }
+        if !self.chain.is_empty() {
+            let chain = self.chain.iter().filter_map(cert_details).map(Value::Object).collect();
+            obj.insert("chain", Value::Array(chain));
+        }
 
         serde_json::to_string(&obj).unwrap_or_else(|_| format!("{{\"msg\":\"{}\",\"code\":1}}", self.msg))
     }
 }
 
+fn cert_details(cert: &X509) -> Option<serde_json::Map<String, Value>> {
+    let mut cert_details = serde_json::Map::new();
+    let der = cert.to_der().ok()?;
+    let der = openssl::base64::encode_block(der.as_slice());
+    cert_details.insert("der".to_string(), Value::String(der));
+    let subject = format_name(cert.subject_name());
+    cert_details.insert("subject".to_string(), Value::String(subject));
+
+    let issuer = format_name(cert.issuer_name());
+    cert_details.insert("issuer".to_string(), Value::String(issuer));
+
+    let expires_on = cert.not_after().to_string();
+    cert_details.insert("expires_on".to_string(), Value::String(expires_on));
+
+    let sha256_sum_bytes = cert.digest(MessageDigest::sha256()).ok()?;
+    let sha256_string = hex::encode(sha256_sum_bytes);
+    cert_details.insert("sha256sum".to_string(), Value::String(sha256_string));
+    Some(cert_details)
+}
+
 impl Display for VerificationError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.msg)
@@ -64,6 +71,7 @@ impl From<std::io::Error> for VerificationError {
     fn from(value: Error) -> Self {
         VerificationError {
             cert: None,
+            chain: Vec::new(),
             msg: value.to_string(),
         }
     }
@@ -73,6 +81,7 @@ impl From<ZipError> for VerificationError {
     fn from(value: ZipError) -> Self {
         VerificationError {
             cert: None,
+            chain: Vec::new(),
             msg: value.to_string(),
         }
     }
@@ -82,6 +91,7 @@ impl From<anyhow::Error> for VerificationError {
     fn from(value: anyhow::Error) -> Self {
         VerificationError {
             cert: None,
+            chain: Vec::new(),
             msg: value.to_string(),
         }
     }
@@ -91,12 +101,26 @@ impl From<ErrorStack> for VerificationError {
     fn from(value: ErrorStack) -> Self {
         VerificationError {
             cert: None,
+            chain: Vec::new(),
             msg: value.to_string(),
         }
     }
 }
 
-fn format_name(name: &X509NameRef) -> String {
+/// a validation failure of a single field of a ConnectionEntry, field is the name used in JSON
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub msg: String,
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.msg)
+    }
+}
+
+pub fn format_name(name: &X509NameRef) -> String {
     let mut parts = VecDeque::new();
     let mut formatted_name = String::with_capacity(128);
     for e in name.entries() {
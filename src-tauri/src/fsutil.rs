@@ -0,0 +1,209 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+/// number of rolling backups kept next to each data file, <file>.bak.1 is the most recent
+pub const BACKUP_COUNT: usize = 3;
+
+/// Replaces the contents of the file at `path` without ever leaving a partially written file behind.
+/// The data is written to a temporary file in the same directory, synced and then renamed over
+/// the target. The previous contents are kept as rolling backups.
+pub fn write_atomic(path: &Path, data: &[u8], backups: usize) -> Result<(), Error> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| Error::msg(format!("{:?} has no parent directory", path)))?;
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| Error::msg(format!("invalid file name {:?}", path)))?;
+
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, Uuid::new_v4()));
+    let r = write_and_sync(&tmp_path, data);
+    if let Err(e) = r {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    if backups > 0 && path.exists() {
+        rotate_backups(path, backups)?;
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(Error::new(e));
+    }
+
+    sync_dir(dir);
+    Ok(())
+}
+
+/// Reads and parses the JSON file at `path`. A file that cannot be parsed is quarantined
+/// (renamed to <file>.corrupt-<timestamp>) and the most recent parseable backup is restored in its place.
+/// Returns None if neither the file nor any of its backups contain data.
+pub fn read_json_or_restore<T: DeserializeOwned>(path: &Path, backups: usize) -> Result<Option<T>, Error> {
+    let err = match read_json(path) {
+        Ok(Some(v)) => return Ok(Some(v)),
+        Ok(None) => None,
+        Err(e) => Some(e),
+    };
+
+    if let Some(ref e) = err {
+        let quarantined = quarantine(path)?;
+        println!("failed to parse {:?}: {}, the file was moved to {:?}", path, e, quarantined);
+    }
+
+    for i in 1..=backups {
+        let bak = backup_path(path, i);
+        match read_json(&bak) {
+            Ok(Some(v)) => {
+                println!("restoring {:?} from backup {:?}", path, bak);
+                fs::copy(&bak, path)?;
+                return Ok(Some(v));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                println!("skipping unreadable backup {:?}: {}", bak, e);
+            }
+        }
+    }
+
+    if err.is_some() {
+        println!("no usable backup found for {:?}", path);
+    }
+    Ok(None)
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut p = path.as_os_str().to_os_string();
+    p.push(format!(".bak.{}", n));
+    PathBuf::from(p)
+}
+
+/// returns Ok(None) when the file is missing or empty
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(path)?;
+    if data.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(None);
+    }
+    let v = serde_json::from_slice(&data)?;
+    Ok(Some(v))
+}
+
+fn write_and_sync(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut f = File::create(path)?;
+    f.write_all(data)?;
+    f.sync_all()?;
+    Ok(())
+}
+
+fn rotate_backups(path: &Path, backups: usize) -> Result<(), Error> {
+    for i in (1..backups).rev() {
+        let from = backup_path(path, i);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, i + 1))?;
+        }
+    }
+    // copy instead of rename so that the file is always present at path
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+fn quarantine(path: &Path) -> Result<PathBuf, Error> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut p = path.as_os_str().to_os_string();
+    p.push(format!(".corrupt-{}", ts));
+    let quarantined = PathBuf::from(p);
+    fs::rename(path, &quarantined)?;
+    Ok(quarantined)
+}
+
+/// makes the rename durable, not supported on Windows
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn create_temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_rolling_backups() {
+        let dir = create_temp_dir();
+        let path = dir.join("data.json");
+        for i in 1..=5 {
+            write_atomic(&path, format!("{{\"v\":{}}}", i).as_bytes(), 3).unwrap();
+        }
+
+        assert_eq!("{\"v\":5}", fs::read_to_string(&path).unwrap());
+        assert_eq!("{\"v\":4}", fs::read_to_string(backup_path(&path, 1)).unwrap());
+        assert_eq!("{\"v\":2}", fs::read_to_string(backup_path(&path, 3)).unwrap());
+        assert!(!backup_path(&path, 4).exists());
+
+        // no temporary files are left behind
+        let count = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(4, count);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_file_is_quarantined_and_restored() {
+        let dir = create_temp_dir();
+        let path = dir.join("data.json");
+        write_atomic(&path, b"{\"a\":\"1\"}", 3).unwrap();
+        write_atomic(&path, b"{\"a\":\"2\"}", 3).unwrap();
+        fs::write(&path, b"{\"a\":").unwrap();
+
+        let data: HashMap<String, String> = read_json_or_restore(&path, 3).unwrap().unwrap();
+        assert_eq!(Some(&String::from("1")), data.get("a"));
+        assert_eq!("{\"a\":\"1\"}", fs::read_to_string(&path).unwrap());
+
+        let quarantined = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .any(|e| e.file_name().to_string_lossy().starts_with("data.json.corrupt-"));
+        assert!(quarantined);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_or_empty_file() {
+        let dir = create_temp_dir();
+        let path = dir.join("data.json");
+        let data: Option<HashMap<String, String>> = read_json_or_restore(&path, 3).unwrap();
+        assert!(data.is_none());
+
+        fs::write(&path, b"").unwrap();
+        let data: Option<HashMap<String, String>> = read_json_or_restore(&path, 3).unwrap();
+        assert!(data.is_none());
+        assert!(path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,487 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Error;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::connection::ConnectionEntry;
+
+/// a problem with a single record of the imported file, the record is either skipped or imported partially
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportWarning {
+    /// the record's name if known, otherwise its position or file name
+    pub record: String,
+    pub msg: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub entries: Vec<ConnectionEntry>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// fields written by the various versions of the Mirth Connect Administrator Launcher
+/// which have no equivalent in ConnectionEntry, they are dropped without a warning
+const IGNORED_MCAL_FIELDS: &[&str] = &[
+    "sslProtocols",
+    "sslCipherSuites",
+    "useLegacyDHSettings",
+    "iconPath",
+    "javaFxHome",
+];
+
+/// Reads the connections from a directory of .jnlp files, a CSV file with the columns name,address,group
+/// or a JSON file written by MCAL or Ballista. Only an unreadable file fails, a malformed record is
+/// reported as a warning.
+pub fn read_import_source(path: &Path) -> Result<ParsedImport, Error> {
+    if path.is_dir() {
+        return read_jnlp_dir(path);
+    }
+
+    let data = fs::read_to_string(path)?;
+    let is_csv = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        Ok(parse_csv(&data))
+    } else {
+        let mut parsed = parse_mcal_json(&data)?;
+        warn_runtime_settings(&mut parsed);
+        Ok(parsed)
+    }
+}
+
+/// Settings that change what runs on this machine are imported as they are, unlike those of a
+/// catalog, so each of them is listed in the preview for the user to review before importing.
+fn warn_runtime_settings(parsed: &mut ParsedImport) {
+    for ce in &parsed.entries {
+        let mut warn = |msg: String| {
+            parsed.warnings.push(ImportWarning {
+                record: ce.name.clone(),
+                msg,
+            })
+        };
+        if let Some(java_args) = ce.java_args.as_deref().filter(|a| !a.trim().is_empty()) {
+            warn(format!("imports the Java arguments {}", java_args));
+        }
+        for (k, v) in &ce.env_vars {
+            warn(format!("imports the environment variable {}={}", k, v));
+        }
+        for (k, v) in &ce.system_properties {
+            warn(format!("imports the system property {}={}", k, v));
+        }
+        if let Some(working_dir) = &ce.working_dir {
+            warn(format!("imports the working directory {}", working_dir));
+        }
+    }
+}
+
+/// Accepts an array of connections (MCAL's connections.json and Ballista's exports), an object
+/// with a connections array, a single connection or Ballista's own data file which maps ids to connections.
+pub fn parse_mcal_json(data: &str) -> Result<ParsedImport, Error> {
+    let root: Value = serde_json::from_str(data)?;
+    let records: Vec<(String, Value)> = match root {
+        Value::Array(records) => records
+            .into_iter()
+            .enumerate()
+            .map(|(i, r)| (format!("#{}", i + 1), r))
+            .collect(),
+        Value::Object(obj) if is_single_entry(&obj) => vec![(String::from("#1"), Value::Object(obj))],
+        Value::Object(mut obj) => match obj.remove("connections") {
+            Some(Value::Array(records)) => records
+                .into_iter()
+                .enumerate()
+                .map(|(i, r)| (format!("#{}", i + 1), r))
+                .collect(),
+            _ => obj.into_iter().collect(),
+        },
+        _ => return Err(Error::msg("expected a JSON array of connections")),
+    };
+
+    let template = template_entry();
+    let mut parsed = ParsedImport::default();
+    for (pos, record) in records {
+        let Value::Object(obj) = record else {
+            parsed.warnings.push(ImportWarning {
+                record: pos,
+                msg: String::from("not a JSON object, skipped"),
+            });
+            continue;
+        };
+        let (ce, warnings) = entry_from_json(&template, &pos, obj);
+        parsed.warnings.extend(warnings);
+        if let Some(ce) = ce {
+            parsed.entries.push(ce);
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// the values of the data file's map are objects, a connection has a string address
+fn is_single_entry(obj: &Map<String, Value>) -> bool {
+    ["address", "url"].iter().any(|k| obj.get(*k).is_some_and(Value::is_string))
+}
+
+fn entry_from_json(template: &ConnectionEntry, pos: &str, mut obj: Map<String, Value>) -> (Option<ConnectionEntry>, Vec<ImportWarning>) {
+    let mut warnings = Vec::new();
+    let name = take_string(&mut obj, &["name"]).unwrap_or_default();
+    let record = if name.trim().is_empty() { pos.to_string() } else { name.clone() };
+    let mut warn = |msg: String| {
+        warnings.push(ImportWarning {
+            record: record.clone(),
+            msg,
+        })
+    };
+
+    let Some(address) = take_string(&mut obj, &["address", "url"]).filter(|a| !a.trim().is_empty()) else {
+        warn(String::from("missing address, skipped"));
+        return (None, warnings);
+    };
+
+    let mut ce = template.clone();
+    ce.name = if name.trim().is_empty() { address.clone() } else { name };
+    ce.address = address.trim().to_string();
+    // a missing id is left empty, it gets generated while planning the import
+    ce.id = take_string(&mut obj, &["id"]).unwrap_or_default().trim().to_string();
+
+    if let Some(heap_size) = take_string(&mut obj, &["heapSize", "maxHeapSize"]) {
+        ce.heap_size = heap_size;
+    }
+    if let Some(java_home) = take_string(&mut obj, &["javaHome"]) {
+        ce.java_home = java_home;
+    }
+    ce.java_args = take_string(&mut obj, &["javaArgs", "jvmArgs"]);
+    ce.icon = take_string(&mut obj, &["icon"]).unwrap_or_default();
+    ce.username = take_string(&mut obj, &["username"]).filter(|u| !u.is_empty());
+    ce.password = take_string(&mut obj, &["password"]).filter(|p| !p.is_empty());
+    if let Some(group) = take_string(&mut obj, &["group"]).filter(|g| !g.trim().is_empty()) {
+        ce.group = group;
+    }
+    if let Some(notes) = take_string(&mut obj, &["notes"]) {
+        ce.notes = notes;
+    }
+
+    for (keys, target) in [
+        (&["verify"][..], &mut ce.verify),
+        (&["donotcache"][..], &mut ce.donotcache),
+        (&["showConsole", "showJavaConsole"][..], &mut ce.show_console),
+    ] {
+        match take_bool(&mut obj, keys) {
+            Ok(Some(v)) => *target = v,
+            Ok(None) => {}
+            Err(v) => warn(format!("ignored invalid value {} of {}", v, keys[0])),
+        }
+    }
+
+    if let Some(v) = obj.remove("lastConnected") {
+        ce.last_connected = v.as_i64();
+    }
+
+    for (key, target) in [
+        ("envVars", &mut ce.env_vars),
+        ("systemProperties", &mut ce.system_properties),
+    ] {
+        match obj.remove(key) {
+            Some(Value::Object(m)) => {
+                for (k, v) in m {
+                    match value_to_string(&v) {
+                        Some(v) => {
+                            target.insert(k, v);
+                        }
+                        None => warn(format!("ignored invalid value of {}.{}", key, k)),
+                    }
+                }
+            }
+            Some(Value::Null) | None => {}
+            Some(v) => warn(format!("ignored invalid value {} of {}", v, key)),
+        }
+    }
+    ce.working_dir = take_string(&mut obj, &["workingDir"]).filter(|w| !w.trim().is_empty());
+
+    let unknown: Vec<&String> = obj
+        .keys()
+        .filter(|k| !IGNORED_MCAL_FIELDS.contains(&k.as_str()))
+        .collect();
+    if !unknown.is_empty() {
+        let unknown: Vec<&str> = unknown.iter().map(|k| k.as_str()).collect();
+        warn(format!("ignored unknown fields {}", unknown.join(", ")));
+    }
+
+    (Some(ce), warnings)
+}
+
+/// Parses rows of name,address,group. The group column is optional and a header row is skipped.
+/// Quoted fields may contain commas, newlines and "" as an escaped quote.
+pub fn parse_csv(data: &str) -> ParsedImport {
+    let template = template_entry();
+    let mut parsed = ParsedImport::default();
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    for (i, row) in reader.records().enumerate() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                parsed.warnings.push(ImportWarning {
+                    record: format!("line {}", line),
+                    msg: format!("invalid CSV row, skipped: {}", e),
+                });
+                continue;
+            }
+        };
+        // the line the row starts on, a quoted field may span several lines
+        let line_num = row.position().map(|p| p.line()).unwrap_or_default();
+        let col = |n: usize| row.get(n).unwrap_or("");
+        if i == 0 && col(0).eq_ignore_ascii_case("name") && col(1).eq_ignore_ascii_case("address") {
+            continue;
+        }
+
+        let record = if col(0).is_empty() { format!("line {}", line_num) } else { col(0).to_string() };
+        if col(1).is_empty() {
+            parsed.warnings.push(ImportWarning {
+                record,
+                msg: format!("missing address on line {}, skipped", line_num),
+            });
+            continue;
+        }
+        if row.len() > 3 {
+            parsed.warnings.push(ImportWarning {
+                record: record.clone(),
+                msg: format!("ignored {} extra columns on line {}", row.len() - 3, line_num),
+            });
+        }
+
+        let mut ce = template.clone();
+        ce.id = Uuid::new_v4().to_string();
+        ce.address = col(1).to_string();
+        ce.name = if col(0).is_empty() { ce.address.clone() } else { col(0).to_string() };
+        if !col(2).is_empty() {
+            ce.group = col(2).to_string();
+        }
+        parsed.entries.push(ce);
+    }
+    parsed
+}
+
+/// Creates a connection for each .jnlp file in the directory using the codebase of the jnlp
+/// element as the address and the file name as the connection name.
+pub fn read_jnlp_dir(dir: &Path) -> Result<ParsedImport, Error> {
+    let mut files = Vec::new();
+    for e in dir.read_dir()? {
+        let path = e?.path();
+        let is_jnlp = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("jnlp"));
+        if is_jnlp && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort_unstable();
+
+    let template = template_entry();
+    let mut parsed = ParsedImport::default();
+    for f in files {
+        let file_name = f
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = f
+            .file_stem()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match read_jnlp_codebase(&f) {
+            Ok(address) => {
+                let mut ce = template.clone();
+                ce.id = Uuid::new_v4().to_string();
+                ce.name = name;
+                ce.address = address;
+                parsed.entries.push(ce);
+            }
+            Err(e) => parsed.warnings.push(ImportWarning {
+                record: file_name,
+                msg: format!("{}, skipped", e),
+            }),
+        }
+    }
+    Ok(parsed)
+}
+
+fn read_jnlp_codebase(path: &Path) -> Result<String, Error> {
+    let data = fs::read_to_string(path)?;
+    let doc = roxmltree::Document::parse(&data)
+        .map_err(|e| Error::msg(format!("invalid JNLP file: {}", e)))?;
+    let jnlp = doc
+        .descendants()
+        .find(|n| n.has_tag_name("jnlp"))
+        .ok_or_else(|| Error::msg("missing jnlp element"))?;
+    let codebase = jnlp
+        .attribute("codebase")
+        .map(|c| c.trim().trim_end_matches('/'))
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| Error::msg("missing codebase attribute"))?;
+    Ok(codebase.to_string())
+}
+
+/// the Java home is resolved later while planning the import, avoid looking it up for every record
+fn template_entry() -> ConnectionEntry {
+    ConnectionEntry {
+        java_home: String::new(),
+        java_args: None,
+        ..Default::default()
+    }
+}
+
+fn take_string(obj: &mut Map<String, Value>, keys: &[&str]) -> Option<String> {
+    let mut found = None;
+    for k in keys {
+        if let Some(v) = obj.remove(*k) {
+            if found.is_none() {
+                found = value_to_string(&v);
+            }
+        }
+    }
+    found
+}
+
+/// returns the offending value if it cannot be read as a bool
+fn take_bool(obj: &mut Map<String, Value>, keys: &[&str]) -> Result<Option<bool>, Value> {
+    let mut found = None;
+    for k in keys {
+        if let Some(v) = obj.remove(*k) {
+            if found.is_some() {
+                continue;
+            }
+            found = match v {
+                Value::Null => None,
+                Value::Bool(b) => Some(b),
+                Value::Number(ref n) if n.as_i64() == Some(0) => Some(false),
+                Value::Number(ref n) if n.as_i64() == Some(1) => Some(true),
+                Value::String(ref s) if s.eq_ignore_ascii_case("true") => Some(true),
+                Value::String(ref s) if s.eq_ignore_ascii_case("false") => Some(false),
+                _ => return Err(v),
+            };
+        }
+    }
+    Ok(found)
+}
+
+fn value_to_string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = "test-resources/import";
+
+    #[test]
+    fn test_parse_mcal_json() {
+        let parsed = read_import_source(&Path::new(FIXTURES).join("mcal-connections.json")).unwrap();
+        let names: Vec<&str> = parsed.entries.iter().map(|ce| ce.name.as_str()).collect();
+        assert_eq!(vec!["Production", "Legacy", "https://qa.example.com:8443"], names);
+
+        let prod = &parsed.entries[0];
+        assert_eq!("f0a1b2c3-0000-4000-8000-000000000001", prod.id);
+        assert_eq!("1024m", prod.heap_size);
+        assert!(prod.show_console);
+        assert_eq!(Some("admin"), prod.username.as_deref());
+
+        let legacy = &parsed.entries[1];
+        assert_eq!("2048", legacy.heap_size);
+        assert_eq!("Default", legacy.group);
+        assert!(legacy.verify);
+        assert!(legacy.id.is_empty());
+
+        let records: Vec<&str> = parsed.warnings.iter().map(|w| w.record.as_str()).collect();
+        assert_eq!(vec!["Legacy", "Legacy", "No address", "#5"], records);
+    }
+
+    #[test]
+    fn test_runtime_settings_are_listed() {
+        let parsed = read_import_source(&Path::new(FIXTURES).join("runtime-settings.json")).unwrap();
+        assert_eq!(1, parsed.entries.len());
+        let msgs: Vec<&str> = parsed.warnings.iter().map(|w| w.msg.as_str()).collect();
+        assert_eq!(
+            vec![
+                "imports the Java arguments -Xss4m",
+                "imports the environment variable JAVA_TOOL_OPTIONS=-javaagent:/tmp/agent.jar",
+                "imports the system property https.proxyHost=proxy.example.com",
+                "imports the system property https.proxyPort=3128",
+                "imports the working directory /opt/mirth",
+            ],
+            msgs
+        );
+        assert!(parsed.warnings.iter().all(|w| w.record == "Tuned"));
+    }
+
+    #[test]
+    fn test_parse_ballista_data_file() {
+        let data = r#"{"abc": {"id": "abc", "name": "local", "address": "https://localhost:8443", "heapSize": "512m"}}"#;
+        let parsed = parse_mcal_json(data).unwrap();
+        assert_eq!(1, parsed.entries.len());
+        assert_eq!("abc", parsed.entries[0].id);
+        assert!(parsed.warnings.is_empty());
+
+        let data = r#"{"id": "abc", "name": "local", "address": "https://localhost:8443", "group": "Test"}"#;
+        let parsed = parse_mcal_json(data).unwrap();
+        assert_eq!(1, parsed.entries.len());
+        assert_eq!("local", parsed.entries[0].name);
+        assert_eq!("Test", parsed.entries[0].group);
+        assert!(parsed.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let parsed = read_import_source(&Path::new(FIXTURES).join("connections.csv")).unwrap();
+        let entries: Vec<(&str, &str, &str)> = parsed
+            .entries
+            .iter()
+            .map(|ce| (ce.name.as_str(), ce.address.as_str(), ce.group.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("prod", "https://prod.example.com:8443", "Production"),
+                ("test, east", "https://test.example.com:8443", "Default"),
+                ("qa \"new\"", "https://qa.example.com:8443", "QA"),
+                ("staging\nwest", "https://staging.example.com:8443", "Staging"),
+            ],
+            entries
+        );
+        let records: Vec<&str> = parsed.warnings.iter().map(|w| w.record.as_str()).collect();
+        assert_eq!(vec!["qa \"new\"", "broken"], records);
+    }
+
+    #[test]
+    fn test_read_jnlp_dir() {
+        let parsed = read_import_source(&Path::new(FIXTURES).join("jnlp")).unwrap();
+        let entries: Vec<(&str, &str)> = parsed
+            .entries
+            .iter()
+            .map(|ce| (ce.name.as_str(), ce.address.as_str()))
+            .collect();
+        assert_eq!(
+            vec![("engine-a", "https://a.example.com:8443"), ("engine-b", "https://b.example.com:8443/mirth")],
+            entries
+        );
+        assert_eq!(1, parsed.warnings.len());
+        assert_eq!("invalid.jnlp", parsed.warnings[0].record);
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::connection::ConnectionEntry;
+use crate::errors::FieldError;
+use crate::validate::{get_java_bin, validate_server_fields};
+use crate::webstart::normalize_url;
+
+/// what to do when an imported connection matches an existing one
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// leave the existing connection as is and skip the imported one
+    KeepMine,
+    /// replace the existing connection's settings with the imported ones
+    TakeTheirs,
+    /// add the imported connection under a new id
+    KeepBoth,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImportOptions {
+    /// only compute the plan, nothing is saved
+    #[serde(default)]
+    pub dry_run: bool,
+    /// keyed by the position of the connection in the imported file, as the ids may be missing,
+    /// takes precedence over default_strategy
+    #[serde(default)]
+    pub strategies: HashMap<usize, MergeStrategy>,
+    /// applied to the duplicates that have no entry in strategies,
+    /// duplicates are reported as conflicts if this is not set
+    pub default_strategy: Option<MergeStrategy>,
+    /// keep the imported javaHome if it exists on this machine instead of using the local one
+    #[serde(default)]
+    pub keep_java_home: bool,
+    /// to decrypt passwords encrypted while exporting
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanEntry {
+    /// the position of the connection in the imported file
+    pub index: usize,
+    pub id: String,
+    pub name: String,
+    pub address: String,
+    /// the id of the existing connection the imported one matched, or of the earlier one in the file for duplicates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub existing_id: Option<String>,
+    /// one of id, address or name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_by: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportPlan {
+    pub added: Vec<PlanEntry>,
+    pub updated: Vec<PlanEntry>,
+    pub skipped: Vec<PlanEntry>,
+    /// duplicates without a merge strategy, nothing gets imported while there are conflicts
+    pub conflicts: Vec<PlanEntry>,
+    /// the connections that appear more than once in the imported file, only the first one is imported
+    pub duplicates: Vec<PlanEntry>,
+    pub invalid: Vec<serde_json::Value>,
+    /// the connections to be saved, with their final ids
+    #[serde(skip)]
+    pub changes: Vec<ConnectionEntry>,
+}
+
+impl ImportPlan {
+    /// Moves a connection to be saved to the invalid ones, e.g. after checking its resolved Java home.
+    pub fn reject(&mut self, id: &str, errors: Vec<FieldError>) {
+        let Some(pos) = self.changes.iter().position(|ce| ce.id == id) else {
+            return;
+        };
+        let ce = self.changes.remove(pos);
+        // the updated connections keep the id of the existing one
+        self.added.retain(|pe| pe.id != id);
+        self.updated.retain(|pe| pe.existing_id.as_deref() != Some(id));
+        self.invalid.push(serde_json::json!({
+            "name": ce.name,
+            "errors": errors,
+        }));
+    }
+}
+
+/// Computes what importing the given connections into the existing ones would do.
+/// `local_java_home` is used for the connections whose Java home is not kept.
+pub fn plan_import(
+    existing: &HashMap<String, Arc<ConnectionEntry>>,
+    incoming: Vec<ConnectionEntry>,
+    options: &ImportOptions,
+    local_java_home: &str,
+) -> ImportPlan {
+    let mut plan = ImportPlan::default();
+    // sorted so that the same connection is matched on every run
+    let mut candidates: Vec<&ConnectionEntry> = existing.values().map(Arc::as_ref).collect();
+    candidates.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut seen: Vec<ConnectionEntry> = Vec::new();
+    for (index, mut ce) in incoming.into_iter().enumerate() {
+        let errors = validate_server_fields(&ce);
+        if !errors.is_empty() {
+            plan.invalid.push(serde_json::json!({
+                "name": ce.name,
+                "errors": errors,
+            }));
+            continue;
+        }
+
+        if let Some((first, by)) = find_duplicate(&seen, &ce) {
+            plan.duplicates.push(PlanEntry {
+                index,
+                id: ce.id.clone(),
+                name: ce.name.clone(),
+                address: ce.address.clone(),
+                existing_id: Some(first.id.clone()),
+                matched_by: Some(by),
+            });
+            continue;
+        }
+        seen.push(ce.clone());
+
+        let found = find_match(&candidates, &ce);
+        let mut pe = PlanEntry {
+            index,
+            id: ce.id.clone(),
+            name: ce.name.clone(),
+            address: ce.address.clone(),
+            existing_id: found.map(|(mine, _)| mine.id.clone()),
+            matched_by: found.map(|(_, by)| by),
+        };
+
+        let Some((mine, _)) = found else {
+            if ce.id.trim().is_empty() {
+                ce.id = Uuid::new_v4().to_string();
+                pe.id = ce.id.clone();
+            }
+            ce.java_home = resolve_java_home(&ce.java_home, local_java_home, options.keep_java_home);
+            plan.added.push(pe);
+            plan.changes.push(ce);
+            continue;
+        };
+
+        let strategy = options
+            .strategies
+            .get(&index)
+            .copied()
+            .or(options.default_strategy);
+        match strategy {
+            None => plan.conflicts.push(pe),
+            Some(MergeStrategy::KeepMine) => plan.skipped.push(pe),
+            Some(MergeStrategy::TakeTheirs) => {
+                // the existing connection keeps its id and the settings that are specific to this machine
+                ce.id = mine.id.clone();
+                ce.java_home = resolve_java_home(&ce.java_home, &mine.java_home, options.keep_java_home);
+                ce.last_connected = mine.last_connected;
+                // the passwords may have been stripped while exporting
+                if ce.password.is_none() {
+                    ce.password = mine.password.clone();
+                }
+                plan.updated.push(pe);
+                plan.changes.push(ce);
+            }
+            Some(MergeStrategy::KeepBoth) => {
+                ce.id = Uuid::new_v4().to_string();
+                if existing.values().any(|e| same_name(&e.name, &ce.name)) {
+                    ce.name = format!("{} (imported)", ce.name);
+                }
+                ce.java_home = resolve_java_home(&ce.java_home, local_java_home, options.keep_java_home);
+                pe.id = ce.id.clone();
+                pe.name = ce.name.clone();
+                plan.added.push(pe);
+                plan.changes.push(ce);
+            }
+        }
+    }
+
+    plan
+}
+
+/// matches by id first, then by the normalized address and finally by name
+fn find_match<'a>(
+    candidates: &[&'a ConnectionEntry],
+    ce: &ConnectionEntry,
+) -> Option<(&'a ConnectionEntry, &'static str)> {
+    let id = ce.id.trim();
+    if let Some(mine) = candidates.iter().find(|e| !id.is_empty() && e.id == id) {
+        return Some((mine, "id"));
+    }
+
+    if let Some(address) = comparable_address(&ce.address) {
+        let mine = candidates
+            .iter()
+            .find(|e| comparable_address(&e.address).as_ref() == Some(&address));
+        if let Some(mine) = mine {
+            return Some((mine, "address"));
+        }
+    }
+
+    candidates
+        .iter()
+        .find(|e| same_name(&e.name, &ce.name))
+        .map(|mine| (*mine, "name"))
+}
+
+/// Unlike find_match, an entry of the same file must have the same address and name to be a duplicate,
+/// e.g. the same server may be listed twice to log in with different users.
+fn find_duplicate<'a>(seen: &'a [ConnectionEntry], ce: &ConnectionEntry) -> Option<(&'a ConnectionEntry, &'static str)> {
+    let id = ce.id.trim();
+    if let Some(first) = seen.iter().find(|e| !id.is_empty() && e.id == id) {
+        return Some((first, "id"));
+    }
+
+    let address = comparable_address(&ce.address);
+    seen.iter()
+        .find(|e| comparable_address(&e.address) == address && same_name(&e.name, &ce.name))
+        .map(|first| (first, "address"))
+}
+
+fn comparable_address(address: &str) -> Option<String> {
+    normalize_url(address.trim())
+        .ok()
+        .map(|(url, _)| url.to_lowercase())
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    let a = a.trim();
+    !a.is_empty() && a.eq_ignore_ascii_case(b.trim())
+}
+
+fn resolve_java_home(imported: &str, fallback: &str, keep_imported: bool) -> String {
+    let imported = imported.trim();
+    if keep_imported && !imported.is_empty() && get_java_bin(Path::new(imported)).is_file() {
+        return imported.to_string();
+    }
+    fallback.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_entry(id: &str, name: &str, address: &str) -> ConnectionEntry {
+        ConnectionEntry {
+            id: id.to_string(),
+            name: name.to_string(),
+            address: address.to_string(),
+            java_home: String::from("/opt/java"),
+            ..Default::default()
+        }
+    }
+
+    fn existing() -> HashMap<String, Arc<ConnectionEntry>> {
+        let mut existing = HashMap::new();
+        for ce in [
+            create_entry("1", "prod", "https://prod.example.com:8443"),
+            create_entry("2", "test", "https://test.example.com:8443"),
+        ] {
+            existing.insert(ce.id.clone(), Arc::new(ce));
+        }
+        existing
+    }
+
+    fn incoming() -> Vec<ConnectionEntry> {
+        vec![
+            create_entry("1", "prod renamed", "https://prod.example.com:8443"),
+            create_entry("a", "other", "HTTPS://TEST.example.com:8443/"),
+            create_entry("b", "TEST", "https://qa.example.com:8443"),
+            create_entry("c", "new", "https://new.example.com:8443"),
+            create_entry("d", "broken", "not a url"),
+        ]
+    }
+
+    #[test]
+    fn test_duplicates_are_detected_by_id_address_and_name() {
+        let plan = plan_import(&existing(), incoming(), &ImportOptions::default(), "/local/java");
+        let matched: Vec<(&str, Option<&str>)> = plan
+            .conflicts
+            .iter()
+            .map(|pe| (pe.id.as_str(), pe.matched_by))
+            .collect();
+        assert_eq!(vec![("1", Some("id")), ("a", Some("address")), ("b", Some("name"))], matched);
+        assert_eq!(1, plan.added.len());
+        assert_eq!("c", plan.added[0].id);
+        assert_eq!(1, plan.invalid.len());
+        assert_eq!("/local/java", plan.changes[0].java_home);
+    }
+
+    #[test]
+    fn test_merge_strategies() {
+        let mut options = ImportOptions {
+            default_strategy: Some(MergeStrategy::KeepMine),
+            ..Default::default()
+        };
+        options.strategies.insert(1, MergeStrategy::TakeTheirs);
+        options.strategies.insert(2, MergeStrategy::KeepBoth);
+
+        let plan = plan_import(&existing(), incoming(), &options, "/local/java");
+        assert!(plan.conflicts.is_empty());
+        assert_eq!(vec!["1"], plan.skipped.iter().map(|pe| pe.id.as_str()).collect::<Vec<_>>());
+
+        // take theirs keeps the existing id and Java home
+        assert_eq!(1, plan.updated.len());
+        let updated = plan.changes.iter().find(|ce| ce.name == "other").unwrap();
+        assert_eq!("2", updated.id);
+        assert_eq!("/opt/java", updated.java_home);
+
+        // keep both gets a new id and name
+        let both = plan.changes.iter().find(|ce| ce.name == "TEST (imported)").unwrap();
+        assert_ne!("b", both.id);
+        assert_eq!(2, plan.added.len());
+    }
+
+    #[test]
+    fn test_stripped_password_and_duplicates_in_file() {
+        let mut existing = existing();
+        let mut prod = (*existing["1"]).clone();
+        prod.password = Some(String::from("mine"));
+        existing.insert(prod.id.clone(), Arc::new(prod));
+
+        // CSV and JNLP imports have no ids
+        let incoming = vec![
+            create_entry("1", "prod", "https://prod.example.com:8443"),
+            create_entry("", "new", "https://new.example.com:8443"),
+            create_entry("", "New", "https://NEW.example.com:8443/"),
+            create_entry("", "new as another user", "https://new.example.com:8443"),
+        ];
+        let options = ImportOptions {
+            default_strategy: Some(MergeStrategy::TakeTheirs),
+            ..Default::default()
+        };
+        let plan = plan_import(&existing, incoming, &options, "/local/java");
+        assert_eq!(Some("mine"), plan.changes[0].password.as_deref());
+        assert_eq!(3, plan.changes.len());
+        assert_eq!(1, plan.duplicates.len());
+        assert_eq!(2, plan.duplicates[0].index);
+        assert_eq!(Some("address"), plan.duplicates[0].matched_by);
+    }
+
+    #[test]
+    fn test_keep_java_home_only_if_it_exists() {
+        let mut ce = create_entry("c", "new", "https://new.example.com:8443");
+        ce.java_home = String::from("/non/existent/java");
+        let options = ImportOptions {
+            keep_java_home: true,
+            ..Default::default()
+        };
+        let plan = plan_import(&existing(), vec![ce], &options, "/local/java");
+        assert_eq!("/local/java", plan.changes[0].java_home);
+
+        assert_eq!("/local/java", resolve_java_home("/opt/jdk", "/local/java", false));
+    }
+}
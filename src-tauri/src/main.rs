@@ -6,23 +6,43 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::exit;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use serde_json::Number;
 use tauri::ipc::Channel;
-use tauri::{AppHandle, Manager, State};
+use tauri::{AppHandle, Emitter, Manager, State};
 
-use crate::connection::{ConnectionEntry, ConnectionStore};
+use crate::catalog::CatalogSource;
+use crate::connection::{ConnectionEntry, ExportOptions, ReloadOutcome};
+use crate::deeplink::{DeepLink, PendingDeepLink};
+use crate::importer::ImportOptions;
+use crate::profile::{ProfileLocation, Profiles};
 use crate::webstart::{WebStartCache, WebstartFile};
 
+mod argpolicy;
+mod catalog;
+mod cli;
 mod connection;
+mod deeplink;
 mod errors;
+mod fsutil;
+mod import_sources;
+mod importer;
+mod managed;
+mod migrate;
+mod profile;
+mod secrets;
+mod validate;
 mod verify;
+mod verifycache;
 mod webstart;
 
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
 
 #[tauri::command]
 async fn get_launcher_info() -> String {
@@ -35,14 +55,18 @@ async fn get_launcher_info() -> String {
 }
 
 #[tauri::command(rename_all = "snake_case")]
-async fn launch(id: String, on_progress: Channel<serde_json::Value>, app: AppHandle, cs: State<'_, ConnectionStore>, wc: State<'_, WebStartCache>) -> Result<String, String> {
+async fn launch(id: String, on_progress: Channel<serde_json::Value>, app: AppHandle, profiles: State<'_, Profiles>, wc: State<'_, WebStartCache>) -> Result<String, String> {
+    let cs = profiles.current();
     let ce = cs.get(&id)
         .ok_or_else(|| format!("connection not found: {}", id))?;
+    let ce = match cs.enforce(ce) {
+        Ok(ce) => ce,
+        Err(e) => return Ok(create_json_resp(-1, &e.to_string())),
+    };
     let cache_dir = cs.cache_dir.clone();
     let cert_store = cs.get_cert_store();
     let address = ce.address.clone();
     let conn_id = ce.id.clone();
-    let conn_name = ce.name.clone();
     let donotcache = ce.donotcache;
     let verify = ce.verify;
 
@@ -52,7 +76,7 @@ async fn launch(id: String, on_progress: Channel<serde_json::Value>, app: AppHan
             let on_progress = on_progress.clone();
             let address = address.clone();
             let cache_dir = cache_dir.clone();
-            move || WebstartFile::load(&address, &cache_dir, donotcache, &conn_id, &conn_name, &on_progress)
+            move || WebstartFile::load(&address, &cache_dir, donotcache, &conn_id, &on_progress)
         }).await.map_err(|e| e.to_string())?;
 
         match tmp {
@@ -67,16 +91,31 @@ async fn launch(id: String, on_progress: Channel<serde_json::Value>, app: AppHan
         }
     }
     let ws = ws.expect("WebstartFile should be loaded at this point");
+    let mut warnings = Vec::new();
     if verify {
         let _ = on_progress.send(serde_json::json!({"message": "Verifying jar signatures..."}));
+        let verify_policy = match cs.get_verify_policy() {
+            Ok(policy) => policy,
+            Err(e) => return Ok(create_json_resp(-1, &e.to_string())),
+        };
         let trusted_certs = cs.get_trusted_certs();
-        let verification_status = ws.verify(cert_store.as_ref(), &trusted_certs);
-        if let Err(e) = verification_status {
-            let resp = e.to_json();
-            println!("{}", resp);
-            return Ok(resp);
+        match ws.verify(cert_store.as_ref(), &trusted_certs, &verify_policy, Some(&cs.get_verify_cache_location())) {
+            Ok(w) => warnings = w,
+            Err(e) => {
+                let resp = e.to_json();
+                println!("{}", resp);
+                return Ok(resp);
+            }
         }
     }
+    let policy = match cs.get_arg_policy() {
+        Ok(policy) => policy,
+        Err(e) => {
+            let msg = e.to_string();
+            println!("{}", msg);
+            return Ok(create_json_resp(-1, &msg));
+        }
+    };
     let _ = on_progress.send(serde_json::json!({"message": "Launching administrator..."}));
     let console_jar = if ce.show_console {
         Some(app.path().resource_dir()
@@ -86,65 +125,293 @@ async fn launch(id: String, on_progress: Channel<serde_json::Value>, app: AppHan
     } else {
         None
     };
-    let r = ws.run(ce, console_jar);
-    if let Err(e) = r {
-        let msg = e.to_string();
-        println!("{}", msg);
-        return Ok(create_json_resp(-1, &msg));
-    }
+    let dropped_args = match ws.run(ce, console_jar, &policy) {
+        Ok(dropped_args) => dropped_args,
+        Err(e) => {
+            let msg = e.to_string();
+            println!("{}", msg);
+            return Ok(create_json_resp(-1, &msg));
+        }
+    };
 
     let _ = cs.update_last_connected(&id);
-    Ok(String::from("{\"code\": 0}"))
+    Ok(serde_json::json!({"code": 0, "dropped_args": dropped_args, "warnings": warnings}).to_string())
 }
 
 #[tauri::command]
-fn get_default_connectionentry(_cs: State<ConnectionStore>) -> Result<serde_json::Value, String> {
+fn get_default_connectionentry() -> Result<serde_json::Value, String> {
     let connection_entry = ConnectionEntry::default();
     Ok(serde_json::json!(connection_entry))
 }
 
 #[tauri::command]
-fn get_all_groups(cs: State<ConnectionStore>) -> Result<serde_json::Value, String> {
+fn get_all_groups(profiles: State<Profiles>) -> Result<serde_json::Value, String> {
+    let cs = profiles.current();
     let groups = cs.get_all_groups().map_err(|e| e.to_string())?;
     Ok(serde_json::json!(groups))
 }
 
 #[tauri::command]
-fn load_connections(cs: State<ConnectionStore>) -> String {
+fn load_connections(profiles: State<Profiles>) -> String {
+    let cs = profiles.current();
     cs.to_json_array_string()
 }
 
 #[tauri::command]
-fn load_single_connection(cs: State<ConnectionStore>, connection_id: String) -> Result<serde_json::Value, String> {
+fn load_single_connection(profiles: State<Profiles>, connection_id: String) -> Result<serde_json::Value, String> {
+    let cs = profiles.current();
     let connection_entry = cs.get(connection_id.as_str())
         .ok_or_else(|| format!("connection not found: {}", connection_id))?;
     Ok(serde_json::json!(connection_entry))
 }
 
 #[tauri::command]
-fn save(ce: &str, cs: State<ConnectionStore>) -> Result<String, String> {
-    let ce: ConnectionEntry = serde_json::from_str(ce)
+async fn save(ce: String, app: AppHandle) -> Result<String, String> {
+    let ce: ConnectionEntry = serde_json::from_str(&ce)
+        .map_err(|e| format!("failed to deserialize ConnectionEntry: {}", e))?;
+    let cs = app.state::<Profiles>().current();
+    // validating checks the Java version by spawning a process
+    tauri::async_runtime::spawn_blocking(move || cs.save(ce))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_connection(ce: String, app: AppHandle) -> Result<serde_json::Value, String> {
+    let ce: ConnectionEntry = serde_json::from_str(&ce)
         .map_err(|e| format!("failed to deserialize ConnectionEntry: {}", e))?;
-    cs.save(ce).map_err(|e| e.to_string())
+    let cs = app.state::<Profiles>().current();
+    // checking the Java version spawns a process, keep it off the main thread
+    let errors = tauri::async_runtime::spawn_blocking(move || cs.validate(&ce))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!(errors))
 }
 
+/// the fields locked by the managed policy, null if there is none
 #[tauri::command]
-fn delete(id: &str, cs: State<ConnectionStore>) -> Result<String, String> {
+fn get_managed_policy(profiles: State<Profiles>) -> serde_json::Value {
+    let cs = profiles.current();
+    serde_json::json!(cs.get_managed_policy().map(|m| m.locked_fields()))
+}
+
+#[tauri::command]
+fn delete(id: &str, profiles: State<Profiles>) -> Result<String, String> {
+    let cs = profiles.current();
     cs.delete(id).map_err(|e| e.to_string())?;
     Ok(String::from("success"))
 }
 
 #[tauri::command(rename_all = "snake_case")]
-fn import(file_path: &str, overwrite: bool, cs: State<ConnectionStore>) -> Result<String, String> {
-    cs.import(file_path, overwrite).map_err(|e| e.to_string())
+async fn import(file_path: String, options: Option<ImportOptions>, app: AppHandle) -> Result<String, String> {
+    let cs = app.state::<Profiles>().current();
+    let options = options.unwrap_or_default();
+    // same as save, the Java homes of the imported connections are checked
+    tauri::async_runtime::spawn_blocking(move || cs.import(&file_path, &options))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn export_connections(ids: Option<Vec<String>>, group: Option<String>, file_path: &str, options: ExportOptions, profiles: State<Profiles>) -> Result<String, String> {
+    let cs = profiles.current();
+    cs.export(ids.as_deref(), group.as_deref(), file_path, &options)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_catalogs(profiles: State<Profiles>) -> serde_json::Value {
+    let cs = profiles.current();
+    cs.get_catalogs()
+}
+
+// catalogs may have to be fetched over the network, these run on a blocking thread
+#[tauri::command]
+async fn add_catalog(name: String, location: String, app: AppHandle) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<Profiles>().current().add_catalog(CatalogSource { name, location })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    Ok(String::from("success"))
+}
+
+#[tauri::command]
+fn remove_catalog(name: &str, profiles: State<Profiles>) -> Result<String, String> {
+    let cs = profiles.current();
+    cs.remove_catalog(name).map_err(|e| e.to_string())?;
+    Ok(String::from("success"))
+}
+
+#[tauri::command]
+async fn reload_catalogs(app: AppHandle) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || app.state::<Profiles>().current().reload_catalogs())
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok(String::from("success"))
 }
 
 #[tauri::command(rename_all = "snake_case")]
-fn trust_cert(cert: &str, cs: State<ConnectionStore>) -> Result<String, String> {
+fn resolve_connections_conflict(use_disk: bool, app: AppHandle, profiles: State<Profiles>) -> Result<String, String> {
+    let cs = profiles.current();
+    cs.resolve_conflict(use_disk).map_err(|e| e.to_string())?;
+    let _ = app.emit("connections-changed", ());
+    Ok(String::from("success"))
+}
+
+#[tauri::command]
+fn get_profiles(profiles: State<Profiles>) -> Result<serde_json::Value, String> {
+    let list = profiles.list().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!(list))
+}
+
+#[tauri::command]
+fn create_profile(name: &str, profiles: State<Profiles>) -> Result<String, String> {
+    profiles.create(name).map_err(|e| e.to_string())?;
+    Ok(String::from("success"))
+}
+
+#[tauri::command]
+fn switch_profile(name: &str, app: AppHandle, profiles: State<Profiles>, wc: State<WebStartCache>) -> Result<String, String> {
+    profiles.switch(name).map_err(|e| e.to_string())?;
+    // the cached files were downloaded into the previous profile's cache directory
+    wc.clear();
+    refresh_catalogs(app);
+    Ok(String::from("success"))
+}
+
+#[tauri::command]
+fn delete_profile(name: &str, profiles: State<Profiles>) -> Result<String, String> {
+    profiles.delete(name).map_err(|e| e.to_string())?;
+    Ok(String::from("success"))
+}
+
+/// Returns the pending ballista:// link, a launch link is resolved to the saved connection.
+#[tauri::command]
+fn take_deep_link(profiles: State<Profiles>, pending: State<PendingDeepLink>) -> Result<Option<serde_json::Value>, String> {
+    let Some(link) = pending.take() else {
+        return Ok(None);
+    };
+    match link {
+        DeepLink::Launch { connection } => {
+            let ce = profiles.current().find(&connection).map_err(|e| e.to_string())?;
+            Ok(Some(serde_json::json!({"action": "launch", "connection": ce})))
+        }
+        DeepLink::Open { .. } => Ok(Some(serde_json::json!(link))),
+    }
+}
+
+/// Called after the user confirmed launching an address received through a ballista://open link.
+#[tauri::command]
+fn open_address(address: &str, profiles: State<Profiles>) -> Result<serde_json::Value, String> {
+    let ce = profiles.current().add_temporary(address).map_err(|e| e.to_string())?;
+    Ok(serde_json::json!(ce))
+}
+
+/// Fetches the remote catalogs in the background, the store is opened with their cached copies.
+fn refresh_catalogs(app: AppHandle) {
+    thread::spawn(move || {
+        let cs = app.state::<Profiles>().current();
+        match cs.reload_catalogs() {
+            Ok(_) => {
+                let _ = app.emit("connections-changed", ());
+            }
+            Err(e) => println!("failed to reload the catalogs: {}", e),
+        }
+    });
+}
+
+/// Hands a link or JNLP file the running instance was asked to open to the UI.
+fn forward_link(app: &AppHandle, location: &str) {
+    match deeplink::parse_opened(location) {
+        Ok(link) => {
+            app.state::<PendingDeepLink>().set(link);
+            let _ = app.emit("deep-link", ());
+        }
+        Err(e) => println!("ignoring the link: {}", e),
+    }
+}
+
+/// Removes the downloaded files of the connections not launched for a while, when the cache policy asks for it.
+fn prune_unused_cache(app: AppHandle) {
+    thread::spawn(move || {
+        let cs = app.state::<Profiles>().current();
+        match cs.get_cache_policy() {
+            Ok(policy) if policy.max_unused_days.is_some() => {
+                if let Err(e) = cs.prune_cache() {
+                    println!("failed to prune the cache: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => println!("{}", e),
+        }
+    });
+}
+
+/// Polls the data file and notifies the UI when it was changed by another program. Polling instead of
+/// file system notifications because the file is replaced by a rename on every write, by Ballista and by
+/// editors or sync tools, which drops a watch on the file itself, and notifications are unreliable on the
+/// network and synced folders the data directory may live in. Hashing a small file every few seconds is cheap.
+fn watch_connections(app: AppHandle) {
+    thread::spawn(move || {
+        let mut conflict_reported = false;
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let cs = app.state::<Profiles>().current();
+            match cs.reload_if_changed() {
+                Ok(ReloadOutcome::Reloaded) => {
+                    conflict_reported = false;
+                    let _ = app.emit("connections-changed", ());
+                }
+                Ok(ReloadOutcome::Conflict) => {
+                    if !conflict_reported {
+                        conflict_reported = true;
+                        let _ = app.emit("connections-conflict", ());
+                    }
+                }
+                Ok(ReloadOutcome::Unchanged) => {
+                    conflict_reported = false;
+                }
+                Ok(ReloadOutcome::Invalid(e)) => {
+                    println!("ignoring the changes to the connections file until it is valid: {}", e);
+                }
+                Err(e) => {
+                    println!("failed to check the connections file for changes: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn trust_cert(cert: &str, profiles: State<Profiles>) -> Result<String, String> {
+    let cs = profiles.current();
     cs.add_trusted_cert(cert).map_err(|e| e.to_string())?;
     Ok(String::from("success"))
 }
 
+/// Release builds on Windows are GUI applications without a console, the output of the commands
+/// is shown in the console they were started from.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // SAFETY: AttachConsole has no preconditions, it fails when there is no parent console or one is already attached
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
 fn main() {
     let env_fix = fix_path_env::fix_vars(&["JAVA_HOME", "PATH"]);
     if let Err(_e) = env_fix {
@@ -152,34 +419,55 @@ fn main() {
     }
 
     let home_directory = home::home_dir().expect("unable to find the path to home directory");
-    // <= 0.2.0 migrate from loose files to .ballista directory
-    let legacy_ballista_dir = home_directory.join(".ballista");
-    let r = fs::create_dir(&legacy_ballista_dir);
-    if let Ok(_) = r {
-        move_file(home_directory.join("catapult-data.json"), legacy_ballista_dir.join("ballista-data.json"));
-        move_file(
-            home_directory.join("catapult-trusted-certs.json"),
-            legacy_ballista_dir.join("ballista-trusted-certs.json"),
-        );
+    let data_directory = profile::data_root(&home_directory);
+    if let Err(e) = fs::create_dir_all(&data_directory) {
+        println!("failed to create the data directory {:?}: {}", data_directory, e);
+        exit(1);
     }
 
-    // >= 2.1.0 migrate from .ballista to .launcher
-    let launcher_directory = home_directory.join(".ballista");
-    if let Err(e) = fs::create_dir(&launcher_directory) {
-        if e.kind() != std::io::ErrorKind::AlreadyExists {
-            println!("failed to create .ballista directory: {}", e);
-            exit(1);
+    let raw_args: Vec<String> = std::env::args().skip(1).collect();
+    let args = cli::parse_args(raw_args.iter().cloned());
+    if !args.command.is_empty() {
+        attach_console();
+        let legacy_home = profile::legacy_home(&home_directory);
+        match ProfileLocation::resolve(legacy_home, data_directory, args.profile.as_deref()) {
+            Ok(location) => exit(cli::run(&args.command, &location)),
+            Err(e) => {
+                eprintln!("failed to open the profile: {}", e);
+                exit(1);
+            }
         }
     }
 
-    let connection_store = ConnectionStore::init(launcher_directory);
-    if let Err(e) = connection_store {
-        println!("failed to initialize ConnectionStore: {}", e.to_string());
+    let profiles = Profiles::init(profile::legacy_home(&home_directory), data_directory, args.profile.as_deref());
+    if let Err(e) = profiles {
+        println!("failed to open the profile: {}", e);
         exit(1);
     }
+    let profiles = profiles.expect("Profiles init was checked above");
+
+    // Linux and Windows start a new instance with the link or file as an argument, macOS sends an Opened event,
+    // when Ballista is already running the new instance hands the argument over to it and exits
+    let pending_link = PendingDeepLink::default();
+    if let Some(link) = deeplink::find_in_args(&raw_args) {
+        match deeplink::parse_opened(link) {
+            Ok(link) => pending_link.set(link),
+            Err(e) => println!("ignoring the link: {}", e),
+        }
+    }
 
     let webcache = WebStartCache::init();
     tauri::Builder::default()
+        // must be the first plugin so that a second instance exits before the others are set up
+        .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.unminimize();
+                let _ = window.set_focus();
+            }
+            if let Some(location) = deeplink::find_in_args(argv.get(1..).unwrap_or_default()) {
+                forward_link(app, &deeplink::resolve_in(location, Path::new(&cwd)));
+            }
+        }))
         .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_clipboard_manager::init())
@@ -189,22 +477,51 @@ fn main() {
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_shell::init())
-        .manage(connection_store.expect("ConnectionStore init was checked above"))
+        .manage(profiles)
         .manage(webcache)
+        .manage(pending_link)
+        .setup(|app| {
+            refresh_catalogs(app.handle().clone());
+            prune_unused_cache(app.handle().clone());
+            watch_connections(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             launch,
             import,
+            export_connections,
             delete,
             save,
+            validate_connection,
+            get_managed_policy,
             get_default_connectionentry,
             get_all_groups,
             load_connections,
             load_single_connection,
             trust_cert,
+            get_catalogs,
+            add_catalog,
+            remove_catalog,
+            reload_catalogs,
+            resolve_connections_conflict,
+            get_profiles,
+            create_profile,
+            switch_profile,
+            delete_profile,
+            take_deep_link,
+            open_address,
             get_launcher_info
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, _event| {
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            if let tauri::RunEvent::Opened { urls } = _event {
+                for url in urls {
+                    forward_link(_app, url.as_str());
+                }
+            }
+        });
 }
 
 fn create_json_resp(code: i32, msg: &str) -> String {
@@ -219,17 +536,3 @@ fn create_json_resp(code: i32, msg: &str) -> String {
     );
     serde_json::to_string(&obj).unwrap_or_default()
 }
-
-fn move_file(old: PathBuf, new: PathBuf) {
-    if old.exists() && !new.exists() {
-        let r = fs::rename(&old, &new);
-        if let Err(e) = r {
-            println!(
-                "failed to move the file from {:?} to {:?} : {}",
-                old,
-                new,
-                e.to_string()
-            );
-        }
-    }
-}
@@ -0,0 +1,241 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::argpolicy::ArgPolicy;
+use crate::connection::ConnectionEntry;
+use crate::errors::FieldError;
+use crate::verify::VerifyPolicy;
+
+/// Environment variables that make the dynamic loader inject libraries into the JVM, they are never set
+/// on a launched administrator while a managed policy is installed.
+const LOADER_ENV_VAR_PREFIXES: &[&str] = &["LD_", "DYLD_"];
+
+/// A policy installed by the administrators of the machine, it locks the settings of all the connections
+/// of all the profiles. Only writable by the administrators, hence there is no way to use another file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManagedPolicy {
+    /// the jars are always verified
+    #[serde(default)]
+    pub force_verify: bool,
+    /// passwords are neither stored nor passed to the administrator
+    #[serde(default)]
+    pub disallow_passwords: bool,
+    /// the hosts the connections may point to, a leading *. matches any subdomain, empty allows all
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// the Java homes that may be used, a trailing * matches any suffix, empty allows all
+    #[serde(default)]
+    pub allowed_java_homes: Vec<String>,
+    #[serde(default)]
+    pub forbid_donotcache: bool,
+    /// replaces the JVM argument policy of the profiles
+    #[serde(default)]
+    pub jvm_args: Option<ArgPolicy>,
+    /// replaces the verification policy of the profiles
+    #[serde(default)]
+    pub verification: Option<VerifyPolicy>,
+}
+
+/// The fields of a connection the user can't change, in the names used in JSON.
+#[derive(Debug, Serialize)]
+pub struct LockedFields {
+    pub locked: Vec<&'static str>,
+    #[serde(rename = "allowedHosts")]
+    pub allowed_hosts: Vec<String>,
+    #[serde(rename = "allowedJavaHomes")]
+    pub allowed_java_homes: Vec<String>,
+}
+
+/// /etc/ballista/policy.json, /Library/Application Support/Ballista/policy.json or %ProgramData%\Ballista\policy.json
+pub fn system_policy_path() -> PathBuf {
+    if cfg!(windows) {
+        let program_data = std::env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into());
+        PathBuf::from(program_data).join("Ballista").join("policy.json")
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from("/Library/Application Support/Ballista/policy.json")
+    } else {
+        PathBuf::from("/etc/ballista/policy.json")
+    }
+}
+
+impl ManagedPolicy {
+    /// None if the file doesn't exist. An invalid file is an error rather than being ignored,
+    /// otherwise a typo would silently unlock everything.
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let f = File::open(path)?;
+        let policy = serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| Error::msg(format!("invalid managed policy {:?}: {}", path, e)))?;
+        println!("using the managed policy {:?}", path);
+        Ok(Some(policy))
+    }
+
+    /// Sets the locked fields to the values required by the policy and removes the loader environment variables.
+    pub fn apply(&self, ce: &mut ConnectionEntry) {
+        ce.env_vars.retain(|name, _| {
+            let denied = is_loader_env_var(name);
+            if denied {
+                println!("dropping the environment variable {} denied by the managed policy", name);
+            }
+            !denied
+        });
+        if self.force_verify {
+            ce.verify = true;
+        }
+        if self.disallow_passwords {
+            ce.password = None;
+        }
+        if self.forbid_donotcache {
+            ce.donotcache = false;
+        }
+    }
+
+    /// The errors for the fields that can't be fixed by apply, e.g. a host that is not allowed.
+    pub fn check(&self, ce: &ConnectionEntry) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if !self.allowed_hosts.is_empty() {
+            let host = Url::parse(ce.address.trim()).ok().and_then(|u| u.host_str().map(str::to_lowercase));
+            let allowed = host.as_deref().is_some_and(|h| self.allowed_hosts.iter().any(|p| host_matches(p, h)));
+            if !allowed {
+                errors.push(FieldError {
+                    field: "address",
+                    msg: format!("the managed policy only allows the hosts {}", self.allowed_hosts.join(", ")),
+                });
+            }
+        }
+
+        if !self.allowed_java_homes.is_empty() {
+            let java_home = ce.java_home.trim().trim_end_matches(['/', '\\']);
+            if !self.allowed_java_homes.iter().any(|p| java_home_matches(p, java_home)) {
+                errors.push(FieldError {
+                    field: "javaHome",
+                    msg: format!("the managed policy only allows the Java homes {}", self.allowed_java_homes.join(", ")),
+                });
+            }
+        }
+
+        errors
+    }
+
+    pub fn locked_fields(&self) -> LockedFields {
+        let mut locked = Vec::new();
+        if self.force_verify {
+            locked.push("verify");
+        }
+        if self.disallow_passwords {
+            locked.push("password");
+        }
+        if self.forbid_donotcache {
+            locked.push("donotcache");
+        }
+        if !self.allowed_hosts.is_empty() {
+            locked.push("address");
+        }
+        if !self.allowed_java_homes.is_empty() {
+            locked.push("javaHome");
+        }
+        if self.jvm_args.is_some() {
+            locked.push("jvmArgPolicy");
+        }
+        if self.verification.is_some() {
+            locked.push("verifyPolicy");
+        }
+        LockedFields {
+            locked,
+            allowed_hosts: self.allowed_hosts.clone(),
+            allowed_java_homes: self.allowed_java_homes.clone(),
+        }
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => host == pattern,
+    }
+}
+
+fn java_home_matches(pattern: &str, java_home: &str) -> bool {
+    let pattern = pattern.trim();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => !java_home.is_empty() && java_home.starts_with(prefix),
+        None => java_home == pattern.trim_end_matches(['/', '\\']),
+    }
+}
+
+fn is_loader_env_var(name: &str) -> bool {
+    let name = name.trim().to_ascii_uppercase();
+    LOADER_ENV_VAR_PREFIXES.iter().any(|p| name.starts_with(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::argpolicy::ArgSource;
+
+    #[test]
+    fn test_managed_policy() {
+        let policy = ManagedPolicy::load(Path::new("test-resources/policy/managed-policy.json"))
+            .unwrap()
+            .unwrap();
+        assert!(ManagedPolicy::load(Path::new("test-resources/policy/missing.json")).unwrap().is_none());
+        assert!(ManagedPolicy::load(Path::new("test-resources/policy/jvm-policy-invalid.json")).is_err());
+
+        let mut ce = ConnectionEntry {
+            address: String::from("https://mirth.prod.example.com:8443"),
+            java_home: String::from("/usr/lib/jvm/temurin-17/"),
+            password: Some(String::from("secret")),
+            verify: false,
+            donotcache: true,
+            env_vars: [
+                ("LD_PRELOAD", "/tmp/evil.so"),
+                ("DYLD_INSERT_LIBRARIES", "/tmp/evil.dylib"),
+                ("ld_library_path", "/tmp"),
+                ("TZ", "UTC"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+        policy.apply(&mut ce);
+        assert_eq!(vec!["TZ"], ce.env_vars.keys().collect::<Vec<_>>());
+        assert!(ce.verify);
+        assert!(!ce.donotcache);
+        assert_eq!(None, ce.password);
+        assert!(policy.check(&ce).is_empty());
+
+        for address in ["https://evil.com:8443", "https://example.com.evil.com", "/tmp/engine.jnlp", "https://mirthXexample.com"] {
+            ce.address = address.to_string();
+            let errors = policy.check(&ce);
+            assert_eq!(1, errors.len(), "{}", address);
+            assert_eq!("address", errors[0].field);
+        }
+        ce.address = String::from("https://MIRTH.INTERNAL/webstart.jnlp");
+        assert!(policy.check(&ce).is_empty());
+
+        for java_home in ["", "/tmp/jdk", "/usr/lib/jvm"] {
+            ce.java_home = java_home.to_string();
+            assert_eq!(1, policy.check(&ce).len(), "{}", java_home);
+        }
+        ce.java_home = String::from("C:\\Program Files\\Java\\jdk-17\\");
+        assert!(policy.check(&ce).is_empty());
+
+        let locked = policy.locked_fields();
+        assert_eq!(vec!["verify", "password", "donotcache", "address", "javaHome", "jvmArgPolicy"], locked.locked);
+        assert!(policy.jvm_args.unwrap().check("-javaagent:/opt/apm/agent.jar", ArgSource::Jnlp).is_some());
+    }
+}
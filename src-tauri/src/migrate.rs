@@ -0,0 +1,375 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use openssl::hash::MessageDigest;
+use openssl::x509::X509;
+use serde_json::{Map, Value};
+
+use crate::fsutil::{read_json_or_restore, write_atomic, BACKUP_COUNT};
+
+/// holds the version of the data stored in the data directory
+const SCHEMA_FILE: &str = "ballista-schema.json";
+const DATA_FILE: &str = "ballista-data.json";
+const TRUSTED_CERTS_FILE: &str = "ballista-trusted-certs.json";
+const CACHE_DIR: &str = "cache";
+
+/// the version written after all the migrations were applied
+pub const SCHEMA_VERSION: u32 = 4;
+
+pub struct MigrationContext {
+    pub home_dir: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+struct Migration {
+    /// the schema version after applying this migration
+    version: u32,
+    description: &'static str,
+    apply: fn(&MigrationContext) -> Result<(), Error>,
+}
+
+/// Ordered chain of migrations, a data directory without a schema file is at version 0.
+/// Each step must work on the data as it was written by the previous version and must never
+/// depend on the current shape of ConnectionEntry.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "move catapult files from the home directory into the data directory",
+        apply: move_catapult_files,
+    },
+    Migration {
+        version: 2,
+        description: "store all the fields of connections explicitly",
+        apply: fill_connection_defaults,
+    },
+    Migration {
+        version: 3,
+        description: "key the trusted certificates by their fingerprint",
+        apply: rekey_trusted_certs,
+    },
+    Migration {
+        version: 4,
+        description: "name the cache directories after the connection id",
+        apply: rename_cache_dirs,
+    },
+];
+
+/// Brings the data directory up to SCHEMA_VERSION and returns the version it is at.
+/// A directory written by a newer version of Ballista is an error, writing to it could lose data.
+pub fn migrate(ctx: &MigrationContext) -> Result<u32, Error> {
+    let mut current = read_schema_version(&ctx.data_dir)?;
+    if current > SCHEMA_VERSION {
+        return Err(Error::msg(format!(
+            "data directory {:?} was written by a newer version of Ballista (schema version {}, supported {})",
+            ctx.data_dir, current, SCHEMA_VERSION
+        )));
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    for m in pending {
+        println!("migrating {:?} to schema version {}: {}", ctx.data_dir, m.version, m.description);
+        (m.apply)(ctx).map_err(|e| {
+            Error::msg(format!("migration to schema version {} failed: {}", m.version, e))
+        })?;
+        write_schema_version(&ctx.data_dir, m.version)?;
+        current = m.version;
+    }
+
+    Ok(current)
+}
+
+fn read_schema_version(data_dir: &Path) -> Result<u32, Error> {
+    let schema: Option<Value> = read_json_or_restore(&data_dir.join(SCHEMA_FILE), BACKUP_COUNT)?;
+    let Some(schema) = schema else {
+        return Ok(0);
+    };
+    let version = schema
+        .get("version")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| Error::msg(format!("missing or invalid version field in {}", SCHEMA_FILE)))?;
+    Ok(version as u32)
+}
+
+pub fn write_schema_version(data_dir: &Path, version: u32) -> Result<(), Error> {
+    let val = serde_json::to_string_pretty(&serde_json::json!({ "version": version }))?;
+    write_atomic(&data_dir.join(SCHEMA_FILE), val.as_bytes(), 0)
+}
+
+/// <= 0.2.0 stored the files directly in the home directory
+fn move_catapult_files(ctx: &MigrationContext) -> Result<(), Error> {
+    move_file(
+        &ctx.home_dir.join("catapult-data.json"),
+        &ctx.data_dir.join(DATA_FILE),
+    )?;
+    move_file(
+        &ctx.home_dir.join("catapult-trusted-certs.json"),
+        &ctx.data_dir.join(TRUSTED_CERTS_FILE),
+    )
+}
+
+/// Fields added over time were only filled in by serde defaults while reading,
+/// entries missing one of the required fields failed to load altogether.
+fn fill_connection_defaults(ctx: &MigrationContext) -> Result<(), Error> {
+    let path = ctx.data_dir.join(DATA_FILE);
+    let data: Option<Map<String, Value>> = read_json_or_restore(&path, BACKUP_COUNT)?;
+    let Some(mut data) = data else {
+        return Ok(());
+    };
+
+    for (id, ce) in data.iter_mut() {
+        let Some(ce) = ce.as_object_mut() else {
+            continue;
+        };
+        let defaults = [
+            ("id", Value::String(id.clone())),
+            ("address", Value::String(String::new())),
+            ("name", Value::String(String::new())),
+            ("heapSize", Value::String(String::from("512m"))),
+            ("icon", Value::String(String::new())),
+            ("javaHome", Value::String(String::new())),
+            ("javaArgs", Value::Null),
+            ("username", Value::Null),
+            ("password", Value::Null),
+            ("verify", Value::Bool(true)),
+            ("group", Value::String(String::from("Default"))),
+            ("notes", Value::String(String::new())),
+            ("donotcache", Value::Bool(false)),
+            ("lastConnected", Value::Null),
+            ("showConsole", Value::Bool(false)),
+            ("envVars", Value::Object(Map::new())),
+            ("systemProperties", Value::Object(Map::new())),
+            ("workingDir", Value::Null),
+        ];
+        for (k, v) in defaults {
+            ce.entry(k).or_insert(v);
+        }
+    }
+
+    let val = serde_json::to_string_pretty(&data)?;
+    write_atomic(&path, val.as_bytes(), BACKUP_COUNT)
+}
+
+/// Trusted certificates were keyed by the digest of their base64 text, which differs for the same
+/// certificate encoded differently. Entries that can't be parsed keep their key, nothing is dropped.
+fn rekey_trusted_certs(ctx: &MigrationContext) -> Result<(), Error> {
+    let path = ctx.data_dir.join(TRUSTED_CERTS_FILE);
+    let data: Option<Map<String, Value>> = read_json_or_restore(&path, BACKUP_COUNT)?;
+    let Some(data) = data else {
+        return Ok(());
+    };
+
+    let mut rekeyed = Map::new();
+    for (key, der_data) in data {
+        let fingerprint = der_data
+            .as_str()
+            .and_then(|b64| openssl::base64::decode_block(b64).ok())
+            .and_then(|der| X509::from_der(der.as_slice()).ok())
+            .map(|cert| cert.digest(MessageDigest::sha256()).map(hex::encode))
+            .transpose()?;
+        match fingerprint {
+            Some(fingerprint) => {
+                rekeyed.insert(fingerprint, der_data);
+            }
+            None => {
+                println!("keeping unparseable trusted certificate with key {} as it is", key);
+                rekeyed.entry(key).or_insert(der_data);
+            }
+        }
+    }
+
+    let val = serde_json::to_string_pretty(&rekeyed)?;
+    write_atomic(&path, val.as_bytes(), BACKUP_COUNT)
+}
+
+/// Cache directories were named `<connection name>_<first 8 chars of the id>` and got orphaned
+/// when a connection was renamed. A directory is only moved when exactly one connection owns it
+/// and the new directory doesn't exist yet, everything else is left in place for prune_cache.
+fn rename_cache_dirs(ctx: &MigrationContext) -> Result<(), Error> {
+    let cache_dir = ctx.data_dir.join(CACHE_DIR);
+    if !cache_dir.is_dir() {
+        return Ok(());
+    }
+
+    let data: Option<Map<String, Value>> = read_json_or_restore(&ctx.data_dir.join(DATA_FILE), BACKUP_COUNT)?;
+    let ids: Vec<String> = data.unwrap_or_default().keys().cloned().collect();
+
+    for e in cache_dir.read_dir()? {
+        let e = e?;
+        if !e.metadata()?.is_dir() {
+            continue;
+        }
+        let name = e.file_name();
+        let Some((_, prefix)) = name.to_str().and_then(|n| n.rsplit_once('_')) else {
+            continue;
+        };
+        if prefix.len() != 8 {
+            continue;
+        }
+        let mut owners = ids.iter().filter(|id| id.starts_with(prefix));
+        let (Some(id), None) = (owners.next(), owners.next()) else {
+            continue;
+        };
+        // same naming as webstart::connection_cache_dir
+        let target: String = id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        let target = cache_dir.join(target);
+        if !target.exists() {
+            fs::rename(e.path(), &target)
+                .map_err(|err| Error::msg(format!("failed to move {:?} to {:?}: {}", e.path(), target, err)))?;
+        }
+    }
+    Ok(())
+}
+
+fn move_file(old: &Path, new: &Path) -> Result<(), Error> {
+    if old.exists() && !new.exists() {
+        fs::rename(old, new)
+            .map_err(|e| Error::msg(format!("failed to move the file from {:?} to {:?}: {}", old, new, e)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    const FIXTURES: &str = "test-resources/migrations";
+
+    /// copies the fixture of the given schema version into a new temporary data directory
+    fn setup(version: &str) -> MigrationContext {
+        let home_dir = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        let data_dir = home_dir.join(".ballista");
+        fs::create_dir_all(&data_dir).unwrap();
+        let target = if version == "v0" { &home_dir } else { &data_dir };
+        copy_dir(&Path::new(FIXTURES).join(version), target);
+        MigrationContext { home_dir, data_dir }
+    }
+
+    fn copy_dir(src: &Path, dst: &Path) {
+        fs::create_dir_all(dst).unwrap();
+        for e in src.read_dir().unwrap() {
+            let e = e.unwrap();
+            let target = dst.join(e.file_name());
+            if e.metadata().unwrap().is_dir() {
+                copy_dir(&e.path(), &target);
+            } else {
+                fs::copy(e.path(), target).unwrap();
+            }
+        }
+    }
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_move_catapult_files() {
+        let ctx = setup("v0");
+        move_catapult_files(&ctx).unwrap();
+        assert!(!ctx.home_dir.join("catapult-data.json").exists());
+        assert!(!ctx.home_dir.join("catapult-trusted-certs.json").exists());
+        let data = read_json(&ctx.data_dir.join(DATA_FILE));
+        assert_eq!("local", data["6c3b4b8e-6f0d-4b0e-9a8e-2f1d6d4b7a10"]["name"]);
+        assert!(ctx.data_dir.join(TRUSTED_CERTS_FILE).exists());
+        fs::remove_dir_all(ctx.home_dir).unwrap();
+    }
+
+    #[test]
+    fn test_fill_connection_defaults() {
+        let ctx = setup("v1");
+        fill_connection_defaults(&ctx).unwrap();
+        let data = read_json(&ctx.data_dir.join(DATA_FILE));
+
+        let local = &data["6c3b4b8e-6f0d-4b0e-9a8e-2f1d6d4b7a10"];
+        // existing values are never overwritten
+        assert_eq!(false, local["verify"]);
+        assert_eq!("Default", local["group"]);
+        assert_eq!(false, local["showConsole"]);
+        assert!(local["envVars"].as_object().unwrap().is_empty());
+
+        let prod = &data["0a4e2f3c-1111-4c22-8d33-9e4455667788"];
+        assert_eq!("0a4e2f3c-1111-4c22-8d33-9e4455667788", prod["id"]);
+        assert_eq!("512m", prod["heapSize"]);
+        assert_eq!("Production", prod["group"]);
+        assert_eq!(true, prod["donotcache"]);
+        assert_eq!(true, prod["verify"]);
+
+        // the result must be readable by the current ConnectionEntry
+        let entries: std::collections::HashMap<String, crate::connection::ConnectionEntry> =
+            serde_json::from_value(data).unwrap();
+        assert_eq!(2, entries.len());
+        fs::remove_dir_all(ctx.home_dir).unwrap();
+    }
+
+    /// fingerprint of the valid certificate in the v2 fixture
+    const CERT_FINGERPRINT: &str = "83640e08978f44e499ceb820a604eebde337a2460126275eaf5392d68d0f299d";
+
+    #[test]
+    fn test_rekey_trusted_certs() {
+        let ctx = setup("v2");
+        rekey_trusted_certs(&ctx).unwrap();
+        let certs = read_json(&ctx.data_dir.join(TRUSTED_CERTS_FILE));
+        let certs = certs.as_object().unwrap();
+        assert_eq!(2, certs.len());
+        assert!(certs.contains_key(CERT_FINGERPRINT));
+        // the unparseable entry is kept as it was
+        assert_eq!("bm90IGEgY2VydA==", certs["0000000000000000000000000000000000000000000000000000000000000000"]);
+        fs::remove_dir_all(ctx.home_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_cache_dirs() {
+        let ctx = setup("v3");
+        rename_cache_dirs(&ctx).unwrap();
+        let cache_dir = ctx.data_dir.join(CACHE_DIR);
+        assert!(cache_dir.join("6c3b4b8e-6f0d-4b0e-9a8e-2f1d6d4b7a10/4_5_2/mirth-client.jar").exists());
+        assert!(!cache_dir.join("local_6c3b4b8e").exists());
+        // an existing directory is never overwritten
+        assert_eq!(
+            "0a4e2f3c-1111-4c22-8d33-9e4455667788/4_5_2\n",
+            fs::read_to_string(cache_dir.join("0a4e2f3c-1111-4c22-8d33-9e4455667788/4_5_2/mirth-client.jar")).unwrap()
+        );
+        // nothing is deleted, unknown directories are left to prune_cache
+        assert!(cache_dir.join("prod_0a4e2f3c/4_5_2/mirth-client.jar").exists());
+        assert!(cache_dir.join("deleted_99999999/4_5_2/mirth-client.jar").exists());
+        assert!(cache_dir.join("localhost_8443/4_5_2/mirth-client.jar").exists());
+        fs::remove_dir_all(ctx.home_dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_full_chain() {
+        let ctx = setup("v0");
+        assert_eq!(SCHEMA_VERSION, migrate(&ctx).unwrap());
+        assert_eq!(SCHEMA_VERSION, read_schema_version(&ctx.data_dir).unwrap());
+
+        let data = read_json(&ctx.data_dir.join(DATA_FILE));
+        assert_eq!("Default", data["6c3b4b8e-6f0d-4b0e-9a8e-2f1d6d4b7a10"]["group"]);
+        let certs = read_json(&ctx.data_dir.join(TRUSTED_CERTS_FILE));
+        assert_eq!(1, certs.as_object().unwrap().len());
+        assert!(certs.get(CERT_FINGERPRINT).is_some());
+
+        // running again is a no-op
+        assert_eq!(SCHEMA_VERSION, migrate(&ctx).unwrap());
+        fs::remove_dir_all(ctx.home_dir).unwrap();
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let ctx = setup("v2");
+        write_schema_version(&ctx.data_dir, SCHEMA_VERSION + 1).unwrap();
+        let before = fs::read(ctx.data_dir.join(TRUSTED_CERTS_FILE)).unwrap();
+        let err = migrate(&ctx).unwrap_err();
+        assert!(err.to_string().contains("newer version"), "{}", err);
+        assert_eq!(SCHEMA_VERSION + 1, read_schema_version(&ctx.data_dir).unwrap());
+        assert_eq!(before, fs::read(ctx.data_dir.join(TRUSTED_CERTS_FILE)).unwrap());
+        fs::remove_dir_all(ctx.home_dir).unwrap();
+    }
+}
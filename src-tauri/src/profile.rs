@@ -0,0 +1,320 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::connection::{ConnectionStore, MachineSettings};
+use crate::fsutil::{read_json_or_restore, write_atomic, BACKUP_COUNT};
+use crate::migrate::{migrate, write_schema_version, MigrationContext, SCHEMA_VERSION};
+
+/// The default profile lives directly in the data directory, so the stores
+/// created before profiles existed keep working without being moved.
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_FILE: &str = "ballista-profiles.json";
+const PROFILES_DIR: &str = "profiles";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ProfilesConfig {
+    active: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileList {
+    pub active: String,
+    pub profiles: Vec<String>,
+}
+
+/// Named sets of connections, trusted certificates and cache, e.g. "prod", "staging" or "customer-x".
+/// Each profile has its own ConnectionStore, only one of them is open at a time.
+pub struct Profiles {
+    /// where the releases before the data directory stored their files, None for a portable install
+    legacy_home: Option<PathBuf>,
+    root: PathBuf,
+    machine: MachineSettings,
+    current: RwLock<(String, Arc<ConnectionStore>)>,
+}
+
+impl Profiles {
+    /// Opens the given profile, or the one that was active the last time when it is None.
+    /// The files of the older releases are moved from `legacy_home` into the default profile.
+    pub fn init(legacy_home: Option<PathBuf>, root: PathBuf, profile: Option<&str>) -> Result<Self, Error> {
+        Self::init_with_machine(legacy_home, root, profile, MachineSettings::system())
+    }
+
+    pub fn init_with_machine(
+        legacy_home: Option<PathBuf>,
+        root: PathBuf,
+        profile: Option<&str>,
+        machine: MachineSettings,
+    ) -> Result<Self, Error> {
+        let location = ProfileLocation::resolve_with_machine(legacy_home, root, profile, machine)?;
+        let cs = location.open()?;
+        Ok(Profiles {
+            legacy_home: location.legacy_home,
+            root: location.root,
+            machine: location.machine,
+            current: RwLock::new((location.name, Arc::new(cs))),
+        })
+    }
+
+    pub fn current(&self) -> Arc<ConnectionStore> {
+        Arc::clone(&self.current.read().expect("profiles lock poisoned").1)
+    }
+
+    pub fn current_name(&self) -> String {
+        self.current.read().expect("profiles lock poisoned").0.clone()
+    }
+
+    pub fn list(&self) -> Result<ProfileList, Error> {
+        let mut profiles = vec![String::from(DEFAULT_PROFILE)];
+        let dir = self.root.join(PROFILES_DIR);
+        if dir.exists() {
+            let mut names = Vec::new();
+            for e in fs::read_dir(dir)? {
+                let e = e?;
+                let name = e.file_name().to_string_lossy().to_string();
+                // the default profile is the root itself, a directory with its name is not a profile
+                if e.file_type()?.is_dir() && name != DEFAULT_PROFILE && validate_profile_name(&name).is_ok() {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            profiles.extend(names);
+        }
+
+        Ok(ProfileList {
+            active: self.current_name(),
+            profiles,
+        })
+    }
+
+    pub fn create(&self, name: &str) -> Result<(), Error> {
+        validate_profile_name(name)?;
+        let dir = data_dir(&self.root, name);
+        if name == DEFAULT_PROFILE || dir.exists() {
+            return Err(Error::msg(format!("profile {} already exists", name)));
+        }
+        fs::create_dir_all(&dir)?;
+        // a new profile has nothing to migrate
+        write_schema_version(&dir, SCHEMA_VERSION)
+    }
+
+    /// Makes the given profile the active one, it is also opened on the next start.
+    pub fn switch(&self, name: &str) -> Result<(), Error> {
+        let cs = open_store(self.legacy_home.as_deref(), &self.root, name, &self.machine)?;
+        *self.current.write().expect("profiles lock poisoned") = (name.to_string(), Arc::new(cs));
+
+        let config = ProfilesConfig {
+            active: Some(name.to_string()),
+        };
+        let val = serde_json::to_string_pretty(&config)?;
+        write_atomic(&self.root.join(PROFILES_FILE), val.as_bytes(), BACKUP_COUNT)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        validate_profile_name(name)?;
+        if name == DEFAULT_PROFILE {
+            return Err(Error::msg("the default profile cannot be deleted"));
+        }
+        if name == self.current_name() {
+            return Err(Error::msg("the active profile cannot be deleted, switch to another profile first"));
+        }
+        let dir = data_dir(&self.root, name);
+        if !dir.exists() {
+            return Err(Error::msg(format!("profile {} does not exist", name)));
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
+
+/// A profile that is not opened yet, the command line opens the store only for the commands that need it.
+pub struct ProfileLocation {
+    legacy_home: Option<PathBuf>,
+    root: PathBuf,
+    name: String,
+    machine: MachineSettings,
+}
+
+impl ProfileLocation {
+    /// Resolves the given profile, or the one that was active the last time when it is None.
+    pub fn resolve(legacy_home: Option<PathBuf>, root: PathBuf, profile: Option<&str>) -> Result<Self, Error> {
+        Self::resolve_with_machine(legacy_home, root, profile, MachineSettings::system())
+    }
+
+    fn resolve_with_machine(
+        legacy_home: Option<PathBuf>,
+        root: PathBuf,
+        profile: Option<&str>,
+        machine: MachineSettings,
+    ) -> Result<Self, Error> {
+        let config: Option<ProfilesConfig> = read_json_or_restore(&root.join(PROFILES_FILE), BACKUP_COUNT)?;
+        let name = match profile {
+            Some(name) => name.to_string(),
+            None => config.and_then(|c| c.active).unwrap_or_else(|| String::from(DEFAULT_PROFILE)),
+        };
+        Ok(ProfileLocation {
+            legacy_home,
+            root,
+            name,
+            machine,
+        })
+    }
+
+    /// The directory of the profile's files, nothing is migrated.
+    pub fn data_dir(&self) -> Result<PathBuf, Error> {
+        validate_profile_name(&self.name)?;
+        let dir = data_dir(&self.root, &self.name);
+        if !dir.is_dir() {
+            return Err(Error::msg(format!("profile {} does not exist", self.name)));
+        }
+        Ok(dir)
+    }
+
+    /// Migrates the profile if needed and opens its store.
+    pub fn open(&self) -> Result<ConnectionStore, Error> {
+        open_store(self.legacy_home.as_deref(), &self.root, &self.name, &self.machine)
+    }
+}
+
+fn open_store(
+    legacy_home: Option<&Path>,
+    root: &Path,
+    name: &str,
+    machine: &MachineSettings,
+) -> Result<ConnectionStore, Error> {
+    validate_profile_name(name)?;
+    let data_dir = data_dir(root, name);
+    if !data_dir.is_dir() {
+        return Err(Error::msg(format!("profile {} does not exist", name)));
+    }
+
+    // only the default profile inherits the files of the older releases stored in the home directory
+    let home_dir = match legacy_home {
+        Some(home_dir) if name == DEFAULT_PROFILE => home_dir,
+        _ => data_dir.as_path(),
+    };
+    let ctx = MigrationContext {
+        home_dir: home_dir.to_path_buf(),
+        data_dir: data_dir.clone(),
+    };
+    migrate(&ctx).map_err(|e| Error::msg(format!("failed to migrate profile {}: {}", name, e)))?;
+    ConnectionStore::init(data_dir, machine)
+}
+
+fn data_dir(root: &Path, name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        root.to_path_buf()
+    } else {
+        root.join(PROFILES_DIR).join(name)
+    }
+}
+
+/// Profile names are used as directory names.
+pub fn validate_profile_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::msg(format!(
+            "invalid profile name '{}', only letters, digits, - and _ are allowed",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// The data directory is ~/.ballista unless BALLISTA_HOME is set, e.g. for a portable install.
+pub fn data_root(home_dir: &Path) -> PathBuf {
+    match portable_root() {
+        Some(dir) => dir,
+        None => home_dir.join(".ballista"),
+    }
+}
+
+/// The home directory whose files of the older releases are migrated, None for a portable install
+/// which must not take over the files of the one installed on the machine.
+pub fn legacy_home(home_dir: &Path) -> Option<PathBuf> {
+    match portable_root() {
+        Some(_) => None,
+        None => Some(home_dir.to_path_buf()),
+    }
+}
+
+fn portable_root() -> Option<PathBuf> {
+    std::env::var_os("BALLISTA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionEntry;
+    use uuid::Uuid;
+
+    /// without a managed policy and a Java home, whatever the machine running the tests has
+    fn test_machine(root: &Path) -> MachineSettings {
+        MachineSettings {
+            policy_path: root.join("no-such-policy.json"),
+            find_java_home: String::new,
+        }
+    }
+
+    #[test]
+    fn test_profiles_have_separate_stores() {
+        let root = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let open = |profile| Profiles::init_with_machine(Some(root.clone()), root.clone(), profile, test_machine(&root));
+        let profiles = open(None).unwrap();
+        assert_eq!(DEFAULT_PROFILE, profiles.current_name());
+
+        let ce = ConnectionEntry {
+            name: String::from("prod-1"),
+            address: String::from("https://localhost:8443"),
+            java_home: String::new(),
+            ..Default::default()
+        };
+        profiles.current().save(ce.clone()).unwrap();
+
+        assert!(profiles.create("../escape").is_err());
+        profiles.create("staging").unwrap();
+        assert!(profiles.create("staging").is_err());
+        profiles.switch("staging").unwrap();
+        assert!(profiles.current().get(&ce.id).is_none());
+        assert!(profiles.delete("staging").is_err());
+
+        // the last active profile is opened again unless one is given
+        let reopened = open(None).unwrap();
+        assert_eq!("staging", reopened.current_name());
+        let reopened = open(Some(DEFAULT_PROFILE)).unwrap();
+        assert!(reopened.current().get(&ce.id).is_some());
+        assert!(open(Some("missing")).is_err());
+
+        profiles.switch(DEFAULT_PROFILE).unwrap();
+        fs::create_dir_all(root.join(PROFILES_DIR).join(DEFAULT_PROFILE)).unwrap();
+        assert_eq!(vec!["default", "staging"], profiles.list().unwrap().profiles);
+        profiles.delete("staging").unwrap();
+        assert_eq!(vec!["default"], profiles.list().unwrap().profiles);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_portable_install_leaves_the_home_directory_alone() {
+        let home = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        let root = home.join("usb");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(home.join("catapult-data.json"), "{}").unwrap();
+
+        Profiles::init_with_machine(None, root.clone(), None, test_machine(&root)).unwrap();
+        assert!(home.join("catapult-data.json").exists());
+        assert!(!root.join("ballista-data.json").exists());
+        fs::remove_dir_all(home).unwrap();
+    }
+}
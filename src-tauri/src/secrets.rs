@@ -0,0 +1,142 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use anyhow::Error;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rustc_hash::FxHashMap;
+
+/// prefix of the passphrase encrypted values, followed by <base64 salt>:<base64 nonce|ciphertext|tag>
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const PBKDF2_ITERATIONS: usize = 600_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Encrypts secrets with AES-256-GCM using a key derived from a passphrase with PBKDF2-HMAC-SHA256.
+/// The key is derived once per instance, all the values encrypted by the same instance share the salt.
+pub struct SecretEncrypter {
+    salt: Vec<u8>,
+    key: Vec<u8>,
+}
+
+/// Decrypts the values produced by SecretEncrypter, caches the derived keys by salt.
+pub struct SecretDecrypter {
+    passphrase: String,
+    keys: FxHashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl SecretEncrypter {
+    pub fn new(passphrase: &str) -> Result<Self, Error> {
+        if passphrase.is_empty() {
+            return Err(Error::msg("passphrase must not be empty"));
+        }
+        let mut salt = vec![0; SALT_LEN];
+        rand_bytes(&mut salt)?;
+        let key = derive_key(passphrase, &salt)?;
+        Ok(SecretEncrypter { salt, key })
+    }
+
+    pub fn encrypt(&self, plain_text: &str) -> Result<String, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        rand_bytes(&mut nonce)?;
+        let mut tag = [0; TAG_LEN];
+        let cipher_text = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            ENCRYPTED_PREFIX.as_bytes(),
+            plain_text.as_bytes(),
+            &mut tag,
+        )?;
+
+        let mut payload = Vec::with_capacity(NONCE_LEN + cipher_text.len() + TAG_LEN);
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&cipher_text);
+        payload.extend_from_slice(&tag);
+        Ok(format!(
+            "{}{}:{}",
+            ENCRYPTED_PREFIX,
+            openssl::base64::encode_block(&self.salt),
+            openssl::base64::encode_block(&payload)
+        ))
+    }
+}
+
+impl SecretDecrypter {
+    pub fn new(passphrase: &str) -> Self {
+        SecretDecrypter {
+            passphrase: passphrase.to_string(),
+            keys: FxHashMap::default(),
+        }
+    }
+
+    pub fn decrypt(&mut self, value: &str) -> Result<String, Error> {
+        let encoded = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or_else(|| Error::msg("value is not encrypted"))?;
+        let (salt, payload) = encoded
+            .split_once(':')
+            .ok_or_else(|| Error::msg("malformed encrypted value"))?;
+        let salt = openssl::base64::decode_block(salt)?;
+        let payload = openssl::base64::decode_block(payload)?;
+        if payload.len() < NONCE_LEN + TAG_LEN {
+            return Err(Error::msg("malformed encrypted value"));
+        }
+
+        if !self.keys.contains_key(&salt) {
+            let key = derive_key(&self.passphrase, &salt)?;
+            self.keys.insert(salt.clone(), key);
+        }
+        let key = &self.keys[&salt];
+
+        let (nonce, rest) = payload.split_at(NONCE_LEN);
+        let (cipher_text, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let plain_text = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            key,
+            Some(nonce),
+            ENCRYPTED_PREFIX.as_bytes(),
+            cipher_text,
+            tag,
+        )
+        .map_err(|_| Error::msg("failed to decrypt, the passphrase may be wrong"))?;
+        Ok(String::from_utf8(plain_text)?)
+    }
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut key = vec![0; KEY_LEN];
+    pbkdf2_hmac(passphrase.as_bytes(), salt, PBKDF2_ITERATIONS, MessageDigest::sha256(), &mut key)?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let enc = SecretEncrypter::new("correct horse").unwrap();
+        let first = enc.encrypt("admin123").unwrap();
+        let second = enc.encrypt("admin123").unwrap();
+        assert!(is_encrypted(&first));
+        assert_ne!(first, second);
+
+        let mut dec = SecretDecrypter::new("correct horse");
+        assert_eq!("admin123", dec.decrypt(&first).unwrap());
+        assert_eq!("admin123", dec.decrypt(&second).unwrap());
+
+        let mut dec = SecretDecrypter::new("wrong");
+        assert!(dec.decrypt(&first).is_err());
+        assert!(dec.decrypt("admin123").is_err());
+    }
+}
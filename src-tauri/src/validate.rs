@@ -0,0 +1,209 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Error;
+use reqwest::Url;
+
+use crate::connection::ConnectionEntry;
+use crate::errors::FieldError;
+use crate::webstart::normalize_url;
+
+/// the admin client requires at least Java 8
+const MIN_JAVA_VERSION: u32 = 8;
+
+/// Checks the fields of the given ConnectionEntry and returns an error for each invalid field.
+/// An empty list means the entry is valid.
+pub fn validate_connection(ce: &ConnectionEntry) -> Vec<FieldError> {
+    let mut errors = validate_server_fields(ce);
+    if let Some(msg) = validate_java_home(&ce.java_home) {
+        errors.push(FieldError { field: "javaHome", msg });
+    }
+
+    errors
+}
+
+/// Same as validate_connection but skips the machine specific fields (e.g. Java home)
+/// which would be expensive to check for every entry while importing.
+pub fn validate_server_fields(ce: &ConnectionEntry) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if let Some(msg) = validate_address(&ce.address) {
+        errors.push(FieldError { field: "address", msg });
+    }
+
+    if let Some(msg) = validate_heap_size(&ce.heap_size) {
+        errors.push(FieldError { field: "heapSize", msg });
+    }
+
+    errors
+}
+
+/// joins the errors into a single message, used when the errors are returned as a plain string
+pub fn format_errors(errors: &[FieldError]) -> String {
+    let msgs: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    format!("invalid connection: {}", msgs.join("; "))
+}
+
+pub(crate) fn validate_address(address: &str) -> Option<String> {
+    let address = address.trim();
+    if address.is_empty() {
+        return Some(String::from("address is required"));
+    }
+
+    if let Err(e) = normalize_url(address) {
+        return Some(format!("invalid address {}: {}", address, e));
+    }
+
+    // normalize_url succeeded, so the address can be parsed
+    let url = Url::parse(address).ok()?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Some(format!("unsupported scheme {}, must be https or http", url.scheme()));
+    }
+
+    if url.host_str().is_none_or(|h| h.is_empty()) {
+        return Some(String::from("address is missing the host name"));
+    }
+
+    None
+}
+
+/// accepts the same syntax as the JVM's -Xmx option, e.g. 1024m, 2G or 536870912
+fn validate_heap_size(heap_size: &str) -> Option<String> {
+    let heap_size = heap_size.trim();
+    if heap_size.is_empty() {
+        // -Xmx is not passed at all
+        return None;
+    }
+
+    let digits = heap_size.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G', 't', 'T']);
+    // only one unit suffix is allowed
+    let valid = !digits.is_empty()
+        && heap_size.len() - digits.len() <= 1
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && digits.parse::<u64>().is_ok_and(|n| n > 0);
+    if !valid {
+        return Some(format!(
+            "invalid heap size {}, must be a number optionally followed by k, m, g or t (e.g. 512m)",
+            heap_size
+        ));
+    }
+
+    None
+}
+
+pub(crate) fn validate_java_home(java_home: &str) -> Option<String> {
+    let java_home = java_home.trim();
+    if java_home.is_empty() {
+        // java from the PATH will be used
+        return None;
+    }
+
+    let jh = Path::new(java_home);
+    if !jh.is_dir() {
+        return Some(format!("Java home {} does not exist", java_home));
+    }
+
+    let java_bin = get_java_bin(jh);
+    if !java_bin.is_file() {
+        return Some(format!("no java executable found at {:?}", java_bin));
+    }
+
+    match get_java_version(&java_bin) {
+        Ok(v) if v < MIN_JAVA_VERSION => Some(format!(
+            "Java version {} is not supported, version {} or higher is required",
+            v, MIN_JAVA_VERSION
+        )),
+        Ok(_) => None,
+        Err(e) => Some(format!("unable to determine the Java version: {}", e)),
+    }
+}
+
+pub fn get_java_bin(java_home: &Path) -> PathBuf {
+    let java = if cfg!(windows) { "java.exe" } else { "java" };
+    java_home.join("bin").join(java)
+}
+
+/// runs `java -version` and returns the major version
+pub fn get_java_version(java_bin: &Path) -> Result<u32, Error> {
+    let out = Command::new(java_bin).arg("-version").output()?;
+    // java prints the version on stderr
+    let text = String::from_utf8_lossy(&out.stderr);
+    parse_java_major_version(&text)
+        .ok_or_else(|| Error::msg(format!("unrecognized output of {:?} -version", java_bin)))
+}
+
+/// parses the output of `java -version`, e.g. `openjdk version "17.0.2" 2022-01-18` or `java version "1.8.0_352"`
+fn parse_java_major_version(output: &str) -> Option<u32> {
+    let start = output.find('"')? + 1;
+    let end = start + output[start..].find('"')?;
+    let version = &output[start..end];
+
+    let mut parts = version.split(['.', '_', '-', '+']);
+    let mut major = parts.next()?;
+    if major == "1" {
+        // 1.8.0 and earlier
+        major = parts.next()?;
+    }
+    major.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_heap_size() {
+        for valid in ["", "512m", "2G", "1024k", "536870912", " 4g "] {
+            assert_eq!(None, validate_heap_size(valid), "{}", valid);
+        }
+
+        for invalid in ["lots", "m", "0", "512mb", "1.5g", "-512m", "512 m"] {
+            assert!(validate_heap_size(invalid).is_some(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_validate_address() {
+        for valid in ["https://localhost:8443", "http://mc.example.com/a/b/"] {
+            assert_eq!(None, validate_address(valid), "{}", valid);
+        }
+
+        for invalid in ["", "localhost:8443", "mc.example.com", "ftp://mc.example.com", "https://"] {
+            assert!(validate_address(invalid).is_some(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_java_major_version() {
+        let candidates = [
+            ("java version \"1.8.0_352\"\nJava(TM) SE Runtime Environment", Some(8)),
+            ("openjdk version \"17.0.2\" 2022-01-18", Some(17)),
+            ("openjdk version \"21-ea\" 2023-09-19", Some(21)),
+            ("openjdk version \"11.0.20+8\"", Some(11)),
+            ("java version \"1.6.0_45\"", Some(6)),
+            ("Error: could not create the Java Virtual Machine", None),
+        ];
+
+        for (output, expected) in candidates {
+            assert_eq!(expected, parse_java_major_version(output), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_validate_connection() {
+        let ce = ConnectionEntry {
+            name: String::from("prod"),
+            address: String::from("localhost"),
+            heap_size: String::from("lots"),
+            java_home: String::from("/non/existent/java/home"),
+            ..Default::default()
+        };
+
+        let errors = validate_connection(&ce);
+        let fields: Vec<&str> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(vec!["address", "heapSize", "javaHome"], fields);
+    }
+}
@@ -2,24 +2,42 @@
 // Copyright (c) Diridium Technologies Inc. All rights reserved.
 // Licensed under the MPL-2.0 License. See LICENSE file in the project root.
 
+use std::collections::BTreeSet;
 use std::fs::File;
-use std::io::{read_to_string, Read};
+use std::io::{BufReader, Read};
 use std::iter::Peekable;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use asn1_rs::{Any, DerSequence, FromDer, Sequence, Set};
-use openssl::cms::CMSOptions;
+use asn1_rs::{Any, Class, DerSequence, FromDer, GeneralizedTime, OctetString, Oid, Sequence, Set, Tag};
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::error::ErrorStack;
+use openssl::cms::{CMSOptions, CmsContentInfo};
 use openssl::md::MdRef;
 use std::str::Chars;
 
-use openssl::x509::store::X509StoreRef;
-use openssl::x509::X509;
+use openssl::ssl::SslFiletype;
+use openssl::stack::{Stack, StackRef};
+use openssl::x509::store::{X509Store, X509StoreBuilder, X509StoreRef};
+use openssl::x509::store::X509Lookup;
+use openssl::x509::verify::{X509VerifyFlags, X509VerifyParam};
+use openssl::x509::{X509Crl, X509PurposeId, X509StoreContext, X509VerifyResult, X509};
+use reqwest::blocking::ClientBuilder;
 use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::errors::VerificationError;
+use crate::errors::{format_name, VerificationError};
+use crate::fsutil::write_atomic;
 use zip::read::ZipFile;
 
 const DIGEST_KEY_SUFFIX: &'static str = "-Digest";
 const DIGEST_MANIFEST_SUFFIX: &'static str = "-Digest-Manifest";
+/// id-aa-timeStampToken, the unsigned attribute holding the RFC 3161 timestamp of the signature
+const TIMESTAMP_TOKEN_OID: &str = "1.2.840.113549.1.9.16.2.14";
+const X509_V_OK: i32 = 0;
+const X509_V_ERR_CERT_REVOKED: i32 = 23;
 
 /// https://docs.oracle.com/en/java/javase/17/docs/specs/jar/jar.html#jar-manifest
 #[derive(Debug)]
@@ -29,6 +47,10 @@ pub struct Manifest {
     digest_alg_name: Option<String>,
     main_attribs: FxHashMap<String, String>,
     name_digests: FxHashMap<String, (String, String)>,
+    /// the digests in the signature files are computed over these bytes
+    raw: Vec<u8>,
+    main_section: Range<usize>,
+    sections: FxHashMap<String, Range<usize>>,
 }
 
 /// https://datatracker.ietf.org/doc/html/rfc5652
@@ -52,54 +74,158 @@ pub struct SignedData<'a> {
     #[tag_implicit(1)]
     #[optional]
     crls: Option<Set<'a>>,
+    signer_infos: Set<'a>,
+}
+
+#[derive(Debug, DerSequence)]
+#[allow(dead_code)]
+pub struct SignerInfo<'a> {
+    version: i32,
+    sid: Any<'a>,
+    digest_algorithm: Sequence<'a>,
+    #[tag_implicit(0)]
+    #[optional]
+    signed_attrs: Option<Set<'a>>,
+    signature_algorithm: Sequence<'a>,
+    signature: OctetString<'a>,
+    #[tag_implicit(1)]
+    #[optional]
+    unsigned_attrs: Option<Set<'a>>,
+}
+
+#[derive(Debug, DerSequence)]
+pub struct Attribute<'a> {
+    attr_type: Oid<'a>,
+    attr_values: Set<'a>,
+}
+
+/// https://datatracker.ietf.org/doc/html/rfc3161#section-2.4.2
+#[derive(Debug, DerSequence)]
+#[allow(dead_code)]
+pub struct TstInfo<'a> {
+    version: i32,
+    policy: Oid<'a>,
+    message_imprint: MessageImprint<'a>,
+    serial_number: Any<'a>,
+    gen_time: GeneralizedTime,
+}
+
+#[derive(Debug, DerSequence)]
+pub struct MessageImprint<'a> {
+    hash_algorithm: Sequence<'a>,
+    hashed_message: OctetString<'a>,
+}
+
+/// The choices made while verifying the jars, read from ballista-verify-policy.json.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct VerifyPolicy {
+    /// accept the SHA-1 digests of the jars signed with older tools, each use is reported as a warning
+    #[serde(default)]
+    pub allow_weak_digests: bool,
+    #[serde(default)]
+    pub revocation: RevocationCheck,
+    /// CRLs, in PEM or DER, used in addition to the ones of the distribution points of the certificates
+    #[serde(default)]
+    pub crl_dir: Option<PathBuf>,
+    /// where the downloaded CRLs are kept until their next update, set by the connection store
+    #[serde(skip)]
+    pub crl_cache_dir: Option<PathBuf>,
+}
+
+/// How the revocation of the signers' certificates is checked with CRLs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationCheck {
+    #[default]
+    Off,
+    /// a CRL that can't be obtained is reported as a warning
+    SoftFail,
+    /// a CRL that can't be obtained fails the verification
+    HardFail,
+}
+
+impl VerifyPolicy {
+    /// The default policy is used when the file doesn't exist, an invalid file fails the verification.
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        if !path.exists() {
+            return Ok(VerifyPolicy::default());
+        }
+        let f = File::open(path)?;
+        serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| anyhow::anyhow!("invalid verification policy {:?}: {}", path, e))
+    }
+}
+
+/// What was checked while verifying a jar, used for auditing jars outside of a launch.
+#[derive(Debug, Default, Serialize)]
+pub struct JarReport {
+    pub jar: String,
+    pub signers: Vec<SignerReport>,
+    /// number of entries whose digests were compared
+    pub entries_checked: usize,
+    pub failures: Vec<String>,
+    /// weak algorithms that were accepted
+    pub warnings: Vec<String>,
+    /// the earliest expiry, in seconds since the epoch, of the certificates the result depends on
+    #[serde(skip)]
+    pub valid_until: Option<i64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SignerReport {
+    pub signature_file: String,
+    pub subject: Option<String>,
+    pub issuer: Option<String>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    pub digest_algorithm: Option<String>,
+    /// the time of the verified timestamp, if the signature was timestamped
+    pub signing_time: Option<String>,
 }
 
 impl Manifest {
-    pub fn parse<R>(file_name: &str, r: R) -> Result<Self, anyhow::Error>
+    pub fn parse<R>(file_name: &str, mut r: R) -> Result<Self, anyhow::Error>
     where
         R: Read,
     {
-        let data = read_to_string(r)?;
-        let mut buf = data.chars().peekable();
+        let mut raw = Vec::with_capacity(1024);
+        r.read_to_end(&mut raw)?;
         let mut main_attribs = FxHashMap::default();
         let mut name_digests = FxHashMap::default();
+        let mut sections = FxHashMap::default();
+        let mut main_section = 0..0;
 
         let mut digest_alg_name = None;
 
-        loop {
-            let l = Manifest::read_line(&mut buf);
-            if l.is_none() {
-                break;
+        for (i, range) in split_sections(&raw).into_iter().enumerate() {
+            let text = std::str::from_utf8(&raw[range.clone()])?;
+            let mut buf = text.chars().peekable();
+            let mut attribs = Vec::new();
+            while let Some(l) = Manifest::read_line(&mut buf).map(Some) {
+                if let Some((k, v)) = Manifest::get_key_val(&l) {
+                    attribs.push((k.to_string(), v.trim().to_string()));
+                }
             }
 
-            let kv = Manifest::get_key_val(&l);
-            let Some((k, v)) = kv else {
-                continue;
-            };
-
-            if k == "Name" {
-                loop {
-                    // until XXX-Digest key is found
-                    let next_line = Manifest::read_line(&mut buf);
-                    if next_line.is_none() {
-                        break;
-                    }
-                    let Some((key, value)) = Manifest::get_key_val(&next_line) else {
-                        break; // a newline encountered and there is no XXX-Digest value for this Name
-                    };
-                    if key.ends_with(DIGEST_KEY_SUFFIX) {
-                        let alg = key.replace(DIGEST_KEY_SUFFIX, "");
-                        let digest = value.trim().to_string();
-                        let class_entry = v.trim().to_string();
-                        name_digests.insert(class_entry, (alg, digest));
-                        break;
+            let name = attribs.iter().find(|(k, _)| k == "Name").map(|(_, v)| v.clone());
+            match name {
+                Some(name) if i > 0 => {
+                    // the first XXX-Digest, the others are alternatives computed with other algorithms
+                    if let Some((k, v)) = attribs.iter().find(|(k, _)| k.ends_with(DIGEST_KEY_SUFFIX)) {
+                        let alg = k.replace(DIGEST_KEY_SUFFIX, "");
+                        name_digests.insert(name.clone(), (alg, v.clone()));
                     }
+                    sections.insert(name, range);
                 }
-            } else {
-                if k.ends_with(DIGEST_MANIFEST_SUFFIX) {
-                    digest_alg_name = Some(k.replace(DIGEST_MANIFEST_SUFFIX, ""));
+                _ => {
+                    for (k, v) in attribs {
+                        if k.ends_with(DIGEST_MANIFEST_SUFFIX) {
+                            digest_alg_name = Some(k.replace(DIGEST_MANIFEST_SUFFIX, ""));
+                        }
+                        main_attribs.insert(k, v);
+                    }
+                    main_section = range;
                 }
-                main_attribs.insert(k.to_string(), v.trim().to_string());
             }
         }
 
@@ -108,6 +234,9 @@ impl Manifest {
             main_attribs,
             name_digests,
             digest_alg_name,
+            raw,
+            main_section,
+            sections,
         })
     }
 
@@ -116,7 +245,8 @@ impl Manifest {
         let space = &' ';
         loop {
             let Some(char) = buf.next() else {
-                return None;
+                // the last line need not end with a newline
+                return if line.is_empty() { None } else { Some(line) };
             };
             match char {
                 '\n' => {
@@ -167,9 +297,87 @@ impl Manifest {
 
         Some((k, v))
     }
+
+    /// the bytes of the main section, including the blank line that ends it
+    fn main_section_bytes(&self) -> &[u8] {
+        &self.raw[self.main_section.clone()]
+    }
+
+    /// the bytes of the section of the given entry, including the blank line that ends it
+    fn section_bytes(&self, name: &str) -> Option<&[u8]> {
+        self.sections.get(name).map(|r| &self.raw[r.clone()])
+    }
+}
+
+/// Splits the manifest into the ranges of its sections, each section ends with a blank line
+/// which is a part of it, as is the case when jarsigner computes the digests of the sections.
+fn split_sections(data: &[u8]) -> Vec<Range<usize>> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let eol_len = match data[i] {
+            b'\r' if data.get(i + 1) == Some(&b'\n') => 2,
+            b'\r' | b'\n' => 1,
+            _ => 0,
+        };
+        if eol_len == 0 {
+            i += 1;
+            continue;
+        }
+
+        let blank = i == line_start;
+        i += eol_len;
+        if blank {
+            // consecutive blank lines don't make empty sections
+            if line_start > start {
+                sections.push(start..i);
+            }
+            start = i;
+        }
+        line_start = i;
+    }
+    if start < data.len() {
+        sections.push(start..data.len());
+    }
+    sections
 }
 
-pub fn verify_jar(file_path: &str, cert_store: &X509StoreRef, trusted_certs: &[X509]) -> Result<(), VerificationError> {
+pub fn verify_jar(
+    file_path: &str,
+    cert_store: &X509StoreRef,
+    trusted_certs: &[X509],
+    policy: &VerifyPolicy,
+) -> Result<(), VerificationError> {
+    let mut report = JarReport::default();
+    verify_jar_with_report(file_path, cert_store, trusted_certs, policy, &mut report)
+}
+
+/// Verifies the jar like verify_jar and records the signers and the number of entries checked in the report,
+/// the verification error, if any, is also added to the report's failures.
+pub fn verify_jar_with_report(
+    file_path: &str,
+    cert_store: &X509StoreRef,
+    trusted_certs: &[X509],
+    policy: &VerifyPolicy,
+    report: &mut JarReport,
+) -> Result<(), VerificationError> {
+    report.jar = file_path.to_string();
+    let r = verify_and_report(file_path, cert_store, trusted_certs, policy, report);
+    if let Err(ref e) = r {
+        report.failures.push(e.msg.clone());
+    }
+    r
+}
+
+fn verify_and_report(
+    file_path: &str,
+    cert_store: &X509StoreRef,
+    trusted_certs: &[X509],
+    policy: &VerifyPolicy,
+    report: &mut JarReport,
+) -> Result<(), VerificationError> {
     let f = File::open(file_path)?;
     let mut za = zip::ZipArchive::new(f)?;
 
@@ -209,10 +417,15 @@ pub fn verify_jar(file_path: &str, cert_store: &X509StoreRef, trusted_certs: &[X
     if signatures.is_empty() {
         return Err(VerificationError {
             cert: None,
+            chain: Vec::new(),
             msg: format!("{} is not signed", file_path),
         });
     }
 
+    // the entries covered by at least one signer, each must match its digest in the manifest
+    let mut signed_entries = BTreeSet::new();
+    // the algorithms accepted for the signature files, their weak ones are already reported
+    let mut digest_refs: FxHashMap<String, &'static MdRef> = FxHashMap::default();
     for (sf_name, sb_prefix) in signatures {
         let mut sigblock: Option<(&str, Vec<u8>)> = None;
         for suffix in ["RSA", "DSA", "EC"] {
@@ -224,158 +437,640 @@ pub fn verify_jar(file_path: &str, cert_store: &X509StoreRef, trusted_certs: &[X
             }
         }
 
-        if let Some((_sig_alg_name, sigblock)) = sigblock {
-            let sigmanifest_buf;
-            {
-                let mut sigmanifest_entry = za.by_name(&sf_name)?;
-                sigmanifest_buf = read_file(&mut sigmanifest_entry)?;
-            }
-            let sigmanifest = Manifest::parse(&sf_name, sigmanifest_buf.as_slice())?;
+        let (sig_alg_name, sigblock) = sigblock.ok_or_else(|| VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: format!("signature block of {} not found in {}", sf_name, file_path),
+        })?;
 
-            let sigblock = sigblock.as_slice();
-            let cert = extract_cert(sigblock)?;
+        let sigmanifest_buf;
+        {
+            let mut sigmanifest_entry = za.by_name(&sf_name)?;
+            sigmanifest_buf = read_file(&mut sigmanifest_entry)?;
+        }
+        let sigmanifest = Manifest::parse(&sf_name, sigmanifest_buf.as_slice())?;
 
-            // Check if the signing cert is already explicitly trusted by the user
-            let is_trusted = cert.as_ref().is_some_and(|c| {
-                trusted_certs.iter().any(|tc| tc.to_der().ok() == c.to_der().ok())
-            });
+        let sigblock = sigblock.as_slice();
+        let signer_certs = extract_certs(sigblock)?;
+        let cert = signer_certs.signer.clone();
+        let chain = signer_certs.full_chain();
+        // the intermediate CAs, the store only has the trusted roots
+        let mut untrusted = Stack::new()?;
+        for c in &signer_certs.intermediates {
+            untrusted.push(c.clone())?;
+        }
 
-            // https://docs.oracle.com/en/java/javase/20/docs/specs/man/jarsigner.html
-            // #1 Verify the signature of the .SF file.
-            println!("verifying {} of {}", sf_name, file_path);
-            let mut cms_info = openssl::cms::CmsContentInfo::from_der(sigblock)?;
-            // If cert is user-trusted, skip chain validation (slow/broken on Windows)
-            // but still verify the signature itself
-            let cms_opts = if is_trusted {
-                CMSOptions::NO_SIGNER_CERT_VERIFY
-            } else {
-                CMSOptions::empty()
-            };
-            let r = cms_info.verify(
-                None,
-                Some(cert_store),
-                Some(sigmanifest_buf.as_slice()),
-                None,
-                cms_opts,
-            );
-            if let Err(e) = r {
-                let msg = e.to_string();
-                println!("verification error: {}", msg);
-                if !msg.contains("certificate purpose") { // could be <[unsupported|unsuitable] certificate purpose>
-                    if msg.contains("cms_signerinfo_verify_cert") {
-                        if !is_trusted {
-                            return Err(VerificationError { cert, msg });
+        // the timestamp proves the signature was made while the signer's certificate was valid
+        let signing_time = match timestamp_token(sigblock)? {
+            Some(ts) => match verify_timestamp(&ts.token, &ts.signature, cert_store, policy, &sf_name, report) {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    let warning = format!("invalid timestamp of {}: {}", sf_name, e.msg);
+                    eprintln!("{}: {}", file_path, warning);
+                    report.warnings.push(warning);
+                    None
+                }
+            },
+            None => None,
+        };
+        report.signers.push(SignerReport {
+            signature_file: sf_name.clone(),
+            subject: cert.as_ref().map(|c| format_name(c.subject_name())),
+            issuer: cert.as_ref().map(|c| format_name(c.issuer_name())),
+            not_before: cert.as_ref().map(|c| c.not_before().to_string()),
+            not_after: cert.as_ref().map(|c| c.not_after().to_string()),
+            digest_algorithm: sigmanifest.digest_alg_name.clone(),
+            signing_time: signing_time.as_ref().map(|t| t.to_string()),
+        });
+
+        if let Some(warning) = cert.as_ref().and_then(|c| check_key_size(c, sig_alg_name)) {
+            eprintln!("{}: {}", file_path, warning);
+            report.warnings.push(format!("{} of {}", warning, sf_name));
+        }
+
+        // Check if the signing cert is already explicitly trusted by the user
+        let is_trusted = cert.as_ref().is_some_and(|c| {
+            trusted_certs.iter().any(|tc| tc.to_der().ok() == c.to_der().ok())
+        });
+
+        // https://docs.oracle.com/en/java/javase/20/docs/specs/man/jarsigner.html
+        // #1 Verify the signature of the .SF file.
+        eprintln!("verifying {} of {}", sf_name, file_path);
+        let mut cms_info = CmsContentInfo::from_der(sigblock)?;
+        // If cert is user-trusted, skip chain validation (slow/broken on Windows)
+        // but still verify the signature itself
+        let cms_opts = if is_trusted {
+            CMSOptions::NO_SIGNER_CERT_VERIFY
+        } else {
+            CMSOptions::empty()
+        };
+        let r = cms_info.verify(
+            Some(&untrusted),
+            Some(cert_store),
+            Some(sigmanifest_buf.as_slice()),
+            None,
+            cms_opts,
+        );
+        if let Err(e) = r {
+            let msg = e.to_string();
+            eprintln!("verification error: {}", msg);
+            if !msg.contains("certificate purpose") { // could be <[unsupported|unsuitable] certificate purpose>
+                if msg.contains("cms_signerinfo_verify_cert") {
+                    if !is_trusted {
+                        let Some(signing_time) = signing_time.as_ref() else {
+                            return Err(VerificationError { cert, chain, msg });
+                        };
+                        // e.g. the certificate expired after signing, it must have been valid at the time of the timestamp
+                        let store = store_at_time(cert_store, signing_time)?;
+                        let r = cms_info.verify(Some(&untrusted), Some(&store), Some(sigmanifest_buf.as_slice()), None, CMSOptions::empty());
+                        if let Err(e) = r {
+                            let msg = format!("{} at the signing time {}", e, &**signing_time);
+                            return Err(VerificationError { cert, chain, msg });
                         }
-                    } else {
-                        return Err(VerificationError { cert: None, msg });
+                        eprintln!("verified the certificate at the signing time {}", &**signing_time);
                     }
+                } else {
+                    return Err(VerificationError { cert: None, chain: Vec::new(), msg });
                 }
             }
+        }
 
-            // #2 Verify the digest listed in each entry in the .SF file with each corresponding section in the manifest.
-            let sig_digest_alg_name = sigmanifest.digest_alg_name.ok_or_else(|| VerificationError {
-                cert: None,
-                msg: String::from("missing XXX-Digest-Manifest attribute"),
-            })?;
+        if !is_trusted && signing_time.is_none() {
+            // the certificates validated at the signing time don't expire as far as this jar is concerned
+            for c in &chain {
+                let t = unix_time(c.not_after())?;
+                report.valid_until = Some(report.valid_until.map_or(t, |v| v.min(t)));
+            }
+        }
 
-            let key = format!("{}{}", sig_digest_alg_name, DIGEST_MANIFEST_SUFFIX);
-            let sf_manifest_digest = sigmanifest.main_attribs.get(&key).ok_or_else(|| VerificationError {
-                cert: None,
-                msg: format!("attribute {} not found in {}", key, sf_name),
-            })?;
+        if let (false, Some(cert)) = (is_trusted, cert.as_ref()) {
+            check_revocation(cert, &untrusted, cert_store, signing_time.as_ref(), policy, &sf_name, report)?;
+        }
 
-            let digest_ref = get_digest_ref(&sig_digest_alg_name).ok_or_else(|| VerificationError {
-                cert: None,
-                msg: format!("unsupported digest algorithm {}", sig_digest_alg_name),
-            })?;
-
-            let mut computed_digest_output: Vec<u8> = vec![0; digest_ref.size()];
-            let computed_digest_output = computed_digest_output.as_mut_slice();
-            // verify that the digests are same
-            let mut ctx = openssl::md_ctx::MdCtx::new()?;
-            ctx.digest_init(digest_ref)?;
-            ctx.digest_update(manifest_buf.as_slice())?;
-            ctx.digest_final(computed_digest_output)?;
-            let computed_manifest_digest = openssl::base64::encode_block(computed_digest_output);
-            if &computed_manifest_digest != sf_manifest_digest {
-                return Err(VerificationError {
+        // #2 Verify the digest listed in each entry in the .SF file with each corresponding section in the manifest.
+        let sig_digest_alg_name = sigmanifest.digest_alg_name.clone().ok_or_else(|| VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: String::from("missing XXX-Digest-Manifest attribute"),
+        })?;
+
+        let key = format!("{}{}", sig_digest_alg_name, DIGEST_MANIFEST_SUFFIX);
+        let sf_manifest_digest = sigmanifest.main_attribs.get(&key).ok_or_else(|| VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: format!("attribute {} not found in {}", key, sf_name),
+        })?;
+
+        let digest_ref = get_digest_ref(&sig_digest_alg_name, policy, &sf_name, report)?;
+        digest_refs.insert(sig_digest_alg_name.to_uppercase(), digest_ref);
+
+        if &compute_digest(digest_ref, &manifest.raw)? == sf_manifest_digest {
+            // the whole manifest is signed, so are all of its entries
+            signed_entries.extend(manifest.name_digests.keys().cloned());
+        } else {
+            // the manifest was changed after signing, e.g. by another signer, only the sections listed in the .SF are signed
+            let key = format!("{}{}-Main-Attributes", sig_digest_alg_name, DIGEST_MANIFEST_SUFFIX);
+            if let Some(main_digest) = sigmanifest.main_attribs.get(&key) {
+                if &compute_digest(digest_ref, manifest.main_section_bytes())? != main_digest {
+                    return Err(VerificationError {
+                        cert: None,
+                        chain: Vec::new(),
+                        msg: format!("mismatch in manifest main attributes digests of {}", file_path),
+                    });
+                }
+            }
+
+            for (name, (alg, sf_digest)) in &sigmanifest.name_digests {
+                let section = manifest.section_bytes(name).ok_or_else(|| VerificationError {
                     cert: None,
-                    msg: format!("mismatch in manifest digests of {}", file_path),
-                });
+                    chain: Vec::new(),
+                    msg: format!("missing MANIFEST entry for {}", name),
+                })?;
+                let digest_ref = get_digest_ref(alg, policy, &sf_name, report)?;
+                digest_refs.insert(alg.to_uppercase(), digest_ref);
+                if &compute_digest(digest_ref, section)? != sf_digest {
+                    return Err(VerificationError {
+                        cert: None,
+                        chain: Vec::new(),
+                        msg: format!("mismatch in manifest digests of {}", file_path),
+                    });
+                }
+                signed_entries.insert(name.clone());
             }
+        }
+        eprintln!("verified");
+    }
 
-            // #3 Read each file in the JAR file that has an entry in the .SF file. While reading, compute the file's digest and
-            // compare the result with the digest for this file in the manifest section. The digests should be the same or verification fails.
-            let mut buf: Vec<u8> = Vec::with_capacity(512);
-            for (jar_entry_name, (jar_entry_digest_alg, _jar_entry_digest)) in
-                &sigmanifest.name_digests
-            {
-                let mut ctx = openssl::md_ctx::MdCtx::new()?;
-                ctx.digest_init(digest_ref)?;
-                let f = za.by_name(jar_entry_name);
-                match f {
-                    Err(e) => {
-                        println!(
-                            "entry {} not found in {} {}",
-                            jar_entry_name, file_path, e
-                        );
-                        continue;
-                    }
-                    Ok(mut f) => {
-                        if f.is_dir() {
-                            println!(
-                                "entry {} of {} is a directory, skipping digest check",
-                                jar_entry_name, file_path
-                            );
-                            continue;
-                        }
-                        f.read_to_end(&mut buf)?;
+    // #3 Every entry must be signed, otherwise classes could be added to a signed jar
+    for name in za.file_names() {
+        if !name.ends_with('/') && !is_signature_related(name) && !signed_entries.contains(name) {
+            return Err(VerificationError {
+                cert: None,
+                chain: Vec::new(),
+                msg: format!("unsigned entry {} in {}", name, file_path),
+            });
+        }
+    }
+
+    // #4 Read each signed file in the JAR file, compute the file's digest and compare the result with the digest
+    // for this file in the manifest section. The digests should be the same or verification fails.
+    let mut buf: Vec<u8> = Vec::with_capacity(512);
+    for jar_entry_name in &signed_entries {
+        let (m_alg, m_digest) = manifest.name_digests.get(jar_entry_name).ok_or_else(|| VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: format!("missing MANIFEST entry for {}", jar_entry_name),
+        })?;
+        let mut f = za.by_name(jar_entry_name).map_err(|_| VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: format!("signed entry {} not found in {}", jar_entry_name, file_path),
+        })?;
+        if f.is_dir() {
+            eprintln!("entry {} of {} is a directory, skipping digest check", jar_entry_name, file_path);
+            continue;
+        }
+        buf.clear();
+        f.read_to_end(&mut buf)?;
+        let digest_ref = match digest_refs.get(&m_alg.to_uppercase()) {
+            Some(d) => *d,
+            None => get_digest_ref(m_alg, policy, &manifest.file_name, report)?,
+        };
+        let computed_digest = compute_digest(digest_ref, &buf)?;
+        report.entries_checked += 1;
+        if m_digest != &computed_digest {
+            let msg = format!(
+                "{} digest mismatch(manifest={} != computed={}) for {} in {}",
+                m_alg, m_digest, computed_digest, jar_entry_name, file_path
+            );
+            return Err(VerificationError { cert: None, chain: Vec::new(), msg });
+        }
+    }
+    Ok(())
+}
+
+fn compute_digest(digest_ref: &MdRef, data: &[u8]) -> Result<String, VerificationError> {
+    let mut output: Vec<u8> = vec![0; digest_ref.size()];
+    let mut ctx = openssl::md_ctx::MdCtx::new()?;
+    ctx.digest_init(digest_ref)?;
+    ctx.digest_update(data)?;
+    ctx.digest_final(output.as_mut_slice())?;
+    Ok(openssl::base64::encode_block(&output))
+}
+
+/// The manifest, the signature files and blocks are not listed in the manifest.
+fn is_signature_related(name: &str) -> bool {
+    let Some(file) = name.strip_prefix("META-INF/") else {
+        return false;
+    };
+    if file.contains('/') {
+        return false;
+    }
+    let upper = file.to_uppercase();
+    upper == "MANIFEST.MF"
+        || upper.starts_with("SIG-")
+        || [".SF", ".RSA", ".DSA", ".EC"].iter().any(|s| upper.ends_with(s))
+}
+
+/// SHA-1 is only accepted when the policy allows the weak digests, SHA and SHA1 are the names older tools used for it.
+fn get_digest_ref(
+    name: &str,
+    policy: &VerifyPolicy,
+    signature_file: &str,
+    report: &mut JarReport,
+) -> Result<&'static MdRef, VerificationError> {
+    use openssl::md::Md;
+    match name.to_uppercase().as_str() {
+        "SHA-256" => Ok(Md::sha256()),
+        "SHA-384" => Ok(Md::sha384()),
+        "SHA-512" => Ok(Md::sha512()),
+        "SHA-1" | "SHA1" | "SHA" if policy.allow_weak_digests => {
+            let warning = format!("{} uses the weak digest algorithm {}", signature_file, name);
+            eprintln!("{}", warning);
+            if !report.warnings.contains(&warning) {
+                report.warnings.push(warning);
+            }
+            Ok(Md::sha1())
+        }
+        "SHA-1" | "SHA1" | "SHA" => Err(VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: format!(
+                "{} uses the weak digest algorithm {}, it is only accepted if the verification policy allows weak digests",
+                signature_file, name
+            ),
+        }),
+        _ => Err(VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: format!("unsupported digest algorithm {}", name),
+        }),
+    }
+}
+
+/// The keys smaller than 2048 bits, or 256 bits for EC, are weak but still accepted.
+fn check_key_size(cert: &X509, sig_alg_name: &str) -> Option<String> {
+    let bits = cert.public_key().ok()?.bits();
+    let min = if sig_alg_name == "EC" { 256 } else { 2048 };
+    if bits < min {
+        return Some(format!("weak {} signing key of {} bits", sig_alg_name, bits));
+    }
+    None
+}
+/// An RFC 3161 timestamp token and the signature it countersigns.
+struct TimestampToken {
+    token: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// The timestamp token in the unsigned attributes of the signer.
+fn timestamp_token(sigblock: &[u8]) -> Result<Option<TimestampToken>, anyhow::Error> {
+    let (_, ci) = ContentInfo::from_der(sigblock)
+        .map_err(|e| anyhow::anyhow!("failed to parse DER content info: {}", e))?;
+    let (_, si) = SignerInfo::from_der(ci.signed_data.signer_infos.content.as_ref())
+        .map_err(|e| anyhow::anyhow!("failed to parse DER signer info: {}", e))?;
+    let Some(attrs) = si.unsigned_attrs else {
+        return Ok(None);
+    };
+
+    let mut data: &[u8] = attrs.content.as_ref();
+    while !data.is_empty() {
+        let (rest, attr) = Attribute::from_der(data)
+            .map_err(|e| anyhow::anyhow!("failed to parse DER attribute: {}", e))?;
+        if attr.attr_type.to_id_string() == TIMESTAMP_TOKEN_OID {
+            return Ok(Some(TimestampToken {
+                token: attr.attr_values.content.to_vec(),
+                signature: si.signature.as_cow().to_vec(),
+            }));
+        }
+        data = rest;
+    }
+    Ok(None)
+}
+
+/// Verifies the RFC 3161 timestamp token of the signature and the TSA's certificate, returns the time of the timestamp.
+fn verify_timestamp(
+    token: &[u8],
+    signature: &[u8],
+    cert_store: &X509StoreRef,
+    policy: &VerifyPolicy,
+    sf_name: &str,
+    report: &mut JarReport,
+) -> Result<Asn1Time, VerificationError> {
+    // the TSA certificates are only meant for timestamping, the S/MIME purpose CMS checks doesn't apply
+    let mut cms_info = CmsContentInfo::from_der(token)?;
+    let mut tst_info = Vec::new();
+    cms_info.verify(None, Some(cert_store), None, Some(&mut tst_info), CMSOptions::NO_SIGNER_CERT_VERIFY)?;
+
+    let tsa_certs = extract_certs(token)?;
+    let tsa_cert = tsa_certs
+        .signer
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("TSA certificate not found in the timestamp"))?;
+    let mut untrusted = Stack::new()?;
+    for c in &tsa_certs.intermediates {
+        untrusted.push(c.clone())?;
+    }
+    let tsa_store = timestamping_store(cert_store)?;
+    let mut ctx = X509StoreContext::new()?;
+    let valid = ctx.init(&tsa_store, &tsa_cert, &untrusted, |c| {
+        Ok(c.verify_cert()?.then_some(()).ok_or_else(|| c.error().error_string()))
+    })?;
+    if let Err(e) = valid {
+        return Err(VerificationError {
+            cert: Some(tsa_cert),
+            chain: tsa_certs.full_chain(),
+            msg: format!("TSA certificate verification failed: {}", e),
+        });
+    }
+
+    let (_, tst) = TstInfo::from_der(&tst_info).map_err(|e| anyhow::anyhow!("failed to parse DER TSTInfo: {}", e))?;
+    let (_, alg) = Oid::from_der(tst.message_imprint.hash_algorithm.content.as_ref())
+        .map_err(|e| anyhow::anyhow!("failed to parse DER message imprint: {}", e))?;
+    let alg = alg.to_id_string();
+    let alg_name = match alg.as_str() {
+        "2.16.840.1.101.3.4.2.1" => "SHA-256",
+        "2.16.840.1.101.3.4.2.2" => "SHA-384",
+        "2.16.840.1.101.3.4.2.3" => "SHA-512",
+        "1.3.14.3.2.26" => "SHA-1",
+        other => other,
+    };
+    let digest_ref = get_digest_ref(alg_name, policy, sf_name, report)?;
+    let imprint = openssl::base64::encode_block(tst.message_imprint.hashed_message.as_cow());
+    if compute_digest(digest_ref, signature)? != imprint {
+        return Err(VerificationError {
+            cert: None,
+            chain: Vec::new(),
+            msg: String::from("the timestamp is not of the signature"),
+        });
+    }
+
+    let t = tst.gen_time.0;
+    let time = Asn1Time::from_str(&format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    ))?;
+    if tsa_cert.not_before() > time || tsa_cert.not_after() < time {
+        return Err(VerificationError {
+            chain: tsa_certs.full_chain(),
+            msg: format!(
+                "the timestamp time {} is outside the validity of the TSA certificate, {} - {}",
+                time.as_ref(),
+                tsa_cert.not_before(),
+                tsa_cert.not_after()
+            ),
+            cert: Some(tsa_cert),
+        });
+    }
+    Ok(time)
+}
+
+/// A copy of the store that only accepts TSA certificates, OpenSSL's timestamping purpose requires a critical
+/// extended key usage with id-kp-timeStamping as its only purpose, as RFC 3161 does.
+fn timestamping_store(cert_store: &X509StoreRef) -> Result<X509Store, VerificationError> {
+    let mut builder = copy_store(cert_store)?;
+    builder.set_purpose(X509PurposeId::TIMESTAMP_SIGN)?;
+    Ok(builder.build())
+}
+
+/// A copy of the store that validates the certificates at the given time instead of now.
+fn store_at_time(cert_store: &X509StoreRef, time: &Asn1Time) -> Result<X509Store, VerificationError> {
+    let mut param = X509VerifyParam::new()?;
+    param.set_time(unix_time(time)? as _);
+
+    let mut builder = copy_store(cert_store)?;
+    builder.set_param(&param)?;
+    Ok(builder.build())
+}
+
+fn unix_time(time: &Asn1TimeRef) -> Result<i64, ErrorStack> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(diff.days as i64 * 86400 + diff.secs as i64)
+}
+
+fn copy_store(cert_store: &X509StoreRef) -> Result<X509StoreBuilder, VerificationError> {
+    let mut builder = X509StoreBuilder::new()?;
+    builder.set_default_paths()?;
+    let certs = cert_store.all_certificates();
+    for c in &certs {
+        // the ones loaded from the default paths are already present
+        let _ = builder.add_cert(c.to_owned());
+    }
+    Ok(builder)
+}
+
+/// Checks the signer's certificate and the CA certificates of its chain against the CRLs of the local directory
+/// and of their distribution points. A revoked certificate always fails, a missing CRL only in the hard-fail mode.
+fn check_revocation(
+    cert: &X509,
+    untrusted: &StackRef<X509>,
+    cert_store: &X509StoreRef,
+    signing_time: Option<&Asn1Time>,
+    policy: &VerifyPolicy,
+    sf_name: &str,
+    report: &mut JarReport,
+) -> Result<(), VerificationError> {
+    if policy.revocation == RevocationCheck::Off {
+        return Ok(());
+    }
+
+    let mut crl_files = Vec::new();
+    if let Some(dir) = &policy.crl_dir {
+        match std::fs::read_dir(dir) {
+            Ok(entries) => {
+                for e in entries {
+                    let path = e?.path();
+                    if path.is_file() {
+                        crl_files.push(path);
                     }
                 }
-                ctx.digest_update(buf.as_slice())?;
-                ctx.digest_final(computed_digest_output)?;
-
-                let computed_digest = openssl::base64::encode_block(&computed_digest_output);
-                let (_m_alg, m_digest) = manifest
-                    .name_digests
-                    .get(jar_entry_name)
-                    .ok_or_else(|| VerificationError {
-                        cert: None,
-                        msg: format!("missing MANIFEST entry for {}", jar_entry_name),
-                    })?;
-                if m_digest != &computed_digest {
-                    let msg = format!(
-                        "{} digest mismatch(manifest={} != computed={}) for {} in {}",
-                        jar_entry_digest_alg, m_digest, computed_digest, jar_entry_name, file_path
-                    );
-                    return Err(VerificationError { cert: None, msg });
+            }
+            // e.g. a network share that is not mounted, the distribution points may still have the CRLs
+            Err(e) => {
+                let msg = format!("failed to read the CRL directory {:?}: {}", dir, e);
+                if policy.revocation == RevocationCheck::HardFail {
+                    return Err(VerificationError { cert: None, chain: Vec::new(), msg });
                 }
-                buf.clear();
+                eprintln!("{}", msg);
+                report.warnings.push(format!("{} for {}", msg, sf_name));
             }
-            println!("verified");
         }
     }
+
+    // the chain was already validated, it is only needed for the distribution points
+    let mut ctx = X509StoreContext::new()?;
+    let chain: Vec<X509> = ctx.init(cert_store, cert, untrusted, |c| {
+        c.verify_cert()?;
+        Ok(c.chain().map(|chain| chain.iter().map(|c| c.to_owned()).collect()).unwrap_or_default())
+    })?;
+    let cache_dir = policy
+        .crl_cache_dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join("ballista-crls"));
+    for url in chain.iter().flat_map(crl_urls) {
+        match fetch_crl(&url, &cache_dir) {
+            Ok(path) => crl_files.push(path),
+            Err(e) => eprintln!("failed to get the CRL {}: {}", url, e),
+        }
+    }
+
+    let mut builder = copy_store(cert_store)?;
+    let mut flags = X509VerifyFlags::CRL_CHECK | X509VerifyFlags::CRL_CHECK_ALL;
+    if signing_time.is_some() {
+        // the certificates were valid at the signing time, they may have expired since
+        flags |= X509VerifyFlags::NO_CHECK_TIME;
+    }
+    builder.set_flags(flags)?;
+    let lookup = builder.add_lookup(X509Lookup::file())?;
+    for path in &crl_files {
+        let is_pem = std::fs::read(path).is_ok_and(|data| data.starts_with(b"-----BEGIN"));
+        let file_type = if is_pem { SslFiletype::PEM } else { SslFiletype::ASN1 };
+        if let Err(e) = lookup.load_crl_file(path, file_type) {
+            eprintln!("failed to load the CRL {:?}: {}", path, e);
+        }
+    }
+    let store = builder.build();
+
+    let mut ctx = X509StoreContext::new()?;
+    let (error, depth) = ctx.init(&store, cert, untrusted, |c| {
+        c.verify_cert()?;
+        Ok((c.error(), c.error_depth()))
+    })?;
+    if error.as_raw() == X509_V_OK {
+        return Ok(());
+    }
+
+    let subject = chain.get(depth as usize).map(|c| format_name(c.subject_name())).unwrap_or_default();
+    let msg = format!("revocation check of {} failed: {}", subject, error.error_string());
+    if error.as_raw() == X509_V_ERR_CERT_REVOKED || policy.revocation == RevocationCheck::HardFail {
+        return Err(VerificationError { cert: None, chain: Vec::new(), msg });
+    }
+    eprintln!("{}", msg);
+    report.warnings.push(format!("{} for {}", msg, sf_name));
     Ok(())
 }
 
-fn get_digest_ref(name: &str) -> Option<&MdRef> {
-    use openssl::md::Md;
-    match name {
-        "SHA-256" => Some(Md::sha256()),
-        "SHA-384" => Some(Md::sha384()),
-        "SHA-512" => Some(Md::sha512()),
-        _ => None,
+/// The HTTP URLs of the CRL distribution points of the certificate.
+fn crl_urls(cert: &X509) -> Vec<String> {
+    let mut urls = Vec::new();
+    for dp in cert.crl_distribution_points().iter().flatten() {
+        let names = dp.distpoint().and_then(|n| n.fullname());
+        for uri in names.iter().flat_map(|n| n.iter()).filter_map(|n| n.uri()) {
+            if uri.starts_with("http://") || uri.starts_with("https://") {
+                urls.push(uri.to_string());
+            }
+        }
+    }
+    urls
+}
+
+/// The cached copy of the CRL until its next update, otherwise the downloaded one which is then cached.
+/// A stale copy is still used when the download fails, the verification reports it as expired.
+fn fetch_crl(url: &str, cache_dir: &Path) -> Result<PathBuf, anyhow::Error> {
+    let cached = cache_dir.join(format!("{}.pem", hex::encode(Sha256::digest(url.as_bytes()))));
+    let fresh = std::fs::read(&cached)
+        .ok()
+        .and_then(|data| X509Crl::from_pem(&data).ok())
+        .and_then(|crl| Some(crl.next_update()? > Asn1Time::days_from_now(0).ok()?));
+    if fresh == Some(true) {
+        return Ok(cached);
+    }
+
+    let download = || -> Result<Vec<u8>, anyhow::Error> {
+        let client = ClientBuilder::default().timeout(Duration::from_secs(10)).build()?;
+        let data = client.get(url).send()?.error_for_status()?.bytes()?;
+        let crl = X509Crl::from_der(&data).or_else(|_| X509Crl::from_pem(&data))?;
+        Ok(crl.to_pem()?)
+    };
+    match download() {
+        Ok(pem) => {
+            std::fs::create_dir_all(cache_dir)?;
+            write_atomic(&cached, &pem, 0)?;
+            eprintln!("downloaded the CRL {}", url);
+            Ok(cached)
+        }
+        Err(e) if fresh.is_some() => {
+            eprintln!("failed to download the CRL {}, using the cached copy: {}", url, e);
+            Ok(cached)
+        }
+        Err(e) => Err(e),
     }
 }
-fn extract_cert(sigblock: &[u8]) -> Result<Option<X509>, anyhow::Error> {
+
+/// The certificates of a PKCS#7 signature block.
+struct SignerCerts {
+    /// identified by the issuer and serial number, or the subject key identifier, of the signer info
+    signer: Option<X509>,
+    /// the other certificates, from the signer's issuer up followed by the unrelated ones
+    intermediates: Vec<X509>,
+}
+
+impl SignerCerts {
+    fn full_chain(&self) -> Vec<X509> {
+        self.signer.iter().chain(self.intermediates.iter()).cloned().collect()
+    }
+}
+
+fn extract_certs(sigblock: &[u8]) -> Result<SignerCerts, anyhow::Error> {
     let (_, ci) = ContentInfo::from_der(sigblock)
         .map_err(|e| anyhow::anyhow!("failed to parse DER content info: {}", e))?;
     //println!("{:?}", ci);
+    let mut certs = Vec::new();
     if let Some(cert_set) = ci.signed_data.certificates {
-        let cert = X509::from_der(cert_set.content.as_ref())?;
-        return Ok(Some(cert));
+        let mut data: &[u8] = cert_set.content.as_ref();
+        while !data.is_empty() {
+            let (rest, _) = Any::from_der(data).map_err(|e| anyhow::anyhow!("failed to parse DER certificate: {}", e))?;
+            // the other certificate formats are of no use
+            if let Ok(cert) = X509::from_der(&data[..data.len() - rest.len()]) {
+                certs.push(cert);
+            }
+            data = rest;
+        }
     }
-    Ok(None)
+
+    let (_, si) = SignerInfo::from_der(ci.signed_data.signer_infos.content.as_ref())
+        .map_err(|e| anyhow::anyhow!("failed to parse DER signer info: {}", e))?;
+    let Some(pos) = certs.iter().position(|c| is_signer(c, &si.sid)) else {
+        return Ok(SignerCerts {
+            signer: None,
+            intermediates: certs,
+        });
+    };
+
+    let signer = certs.remove(pos);
+    let mut intermediates = Vec::with_capacity(certs.len());
+    let mut current = signer.clone();
+    while let Some(pos) = certs.iter().position(|c| c.issued(&current) == X509VerifyResult::OK) {
+        current = certs.remove(pos);
+        intermediates.push(current.clone());
+    }
+    intermediates.append(&mut certs);
+    Ok(SignerCerts {
+        signer: Some(signer),
+        intermediates,
+    })
+}
+
+/// https://datatracker.ietf.org/doc/html/rfc5652#section-5.3 the SignerIdentifier
+fn is_signer(cert: &X509, sid: &Any) -> bool {
+    if sid.header.class() == Class::ContextSpecific && sid.header.tag() == Tag(0) {
+        return cert.subject_key_id().is_some_and(|id| id.as_slice() == sid.data);
+    }
+    if sid.header.tag() != Tag::Sequence {
+        return false;
+    }
+
+    // issuerAndSerialNumber
+    let Ok((rest, _)) = Any::from_der(sid.data) else {
+        return false;
+    };
+    let issuer = &sid.data[..sid.data.len() - rest.len()];
+    let Ok((_, serial)) = Any::from_der(rest) else {
+        return false;
+    };
+    let serial: Vec<u8> = serial.data.iter().copied().skip_while(|b| *b == 0).collect();
+    cert.issuer_name().to_der().is_ok_and(|name| name == issuer)
+        && cert.serial_number().to_bn().is_ok_and(|bn| bn.to_vec() == serial)
 }
 
 fn read_file(zf: &mut ZipFile) -> Result<Vec<u8>, anyhow::Error> {
@@ -505,7 +1200,7 @@ mod tests {
         let jar_file = "test-resources/valid-signed.jar";
         let mut xb = X509StoreBuilder::new().unwrap();
         let store = xb.build();
-        let r = verify_jar(jar_file, store.as_ref(), &[]);
+        let r = verify_jar(jar_file, store.as_ref(), &[], &VerifyPolicy::default());
         println!("{:?}", r);
         assert!(r.is_err());
         let ve = r.err().unwrap();
@@ -515,7 +1210,7 @@ mod tests {
         let mut xb = X509StoreBuilder::new().unwrap();
         xb.add_cert(cert).unwrap();
         let store = xb.build();
-        let r = verify_jar(jar_file, store.as_ref(), &trusted);
+        let r = verify_jar(jar_file, store.as_ref(), &trusted, &VerifyPolicy::default());
         println!("{:?}", r);
         assert!(r.is_ok());
     }
@@ -529,8 +1224,320 @@ mod tests {
         let mut xb = X509StoreBuilder::new().unwrap();
         let store = xb.build();
         for f in files {
-            let r = verify_jar(f, store.as_ref(), &[]);
+            let r = verify_jar(f, store.as_ref(), &[], &VerifyPolicy::default());
             assert!(r.is_err());
         }
     }
+
+    #[test]
+    fn test_verify_report() {
+        let jar_file = "test-resources/valid-signed.jar";
+        let store = X509StoreBuilder::new().unwrap().build();
+        let mut report = JarReport::default();
+        let cert = verify_jar_with_report(jar_file, store.as_ref(), &[], &VerifyPolicy::default(), &mut report)
+            .unwrap_err()
+            .cert
+            .unwrap();
+        assert_eq!(1, report.failures.len());
+
+        let mut xb = X509StoreBuilder::new().unwrap();
+        xb.add_cert(cert.clone()).unwrap();
+        let store = xb.build();
+        let mut report = JarReport::default();
+        verify_jar_with_report(jar_file, store.as_ref(), &[cert], &VerifyPolicy::default(), &mut report).unwrap();
+        assert!(report.failures.is_empty());
+        assert_eq!(1, report.signers.len());
+        let signer = &report.signers[0];
+        assert_eq!(Some("SHA-256"), signer.digest_algorithm.as_deref());
+        assert!(signer.subject.is_some());
+        assert!(signer.not_after.is_some());
+        assert!(report.entries_checked > 0);
+
+        let mut report = JarReport::default();
+        assert!(verify_jar_with_report("test-resources/tampered-app-class.jar", store.as_ref(), &[], &VerifyPolicy::default(), &mut report).is_err());
+        assert_eq!(1, report.failures.len());
+    }
+
+    /// the signer's certificate of a jar signed with a self-signed certificate
+    fn self_signed_cert(jar_file: &str) -> X509 {
+        let store = X509StoreBuilder::new().unwrap().build();
+        let policy = VerifyPolicy {
+            allow_weak_digests: true,
+            ..Default::default()
+        };
+        verify_jar(jar_file, store.as_ref(), &[], &policy).unwrap_err().cert.unwrap()
+    }
+
+    #[test]
+    fn test_verify_ec_and_dsa_signed() {
+        for jar_file in ["test-resources/ec-signed.jar", "test-resources/dsa-sha1-signed.jar"] {
+            let cert = self_signed_cert(jar_file);
+            let mut xb = X509StoreBuilder::new().unwrap();
+            xb.add_cert(cert.clone()).unwrap();
+            let store = xb.build();
+            let trusted = [cert];
+
+            let mut report = JarReport::default();
+            let weak_allowed = VerifyPolicy {
+                allow_weak_digests: true,
+                ..Default::default()
+            };
+            verify_jar_with_report(jar_file, store.as_ref(), &trusted, &weak_allowed, &mut report).unwrap();
+            assert!(report.entries_checked > 0);
+
+            let r = verify_jar(jar_file, store.as_ref(), &trusted, &VerifyPolicy::default());
+            if jar_file.contains("sha1") {
+                assert_eq!(Some("SHA-1"), report.signers[0].digest_algorithm.as_deref());
+                assert_eq!(
+                    vec![
+                        "weak DSA signing key of 1024 bits of META-INF/DSA.SF",
+                        "META-INF/DSA.SF uses the weak digest algorithm SHA-1",
+                    ],
+                    report.warnings
+                );
+                assert!(r.unwrap_err().msg.contains("weak digest algorithm SHA-1"));
+            } else {
+                assert_eq!(Some("SHA-256"), report.signers[0].digest_algorithm.as_deref());
+                assert!(report.warnings.is_empty());
+                assert!(r.is_ok());
+            }
+        }
+    }
+
+    /// verifies the jar trusting the self-signed certificates of all its signers
+    fn verify_trusting_signers(jar_file: &str, report: &mut JarReport) -> Result<(), VerificationError> {
+        let mut trusted: Vec<X509> = Vec::new();
+        loop {
+            let mut xb = X509StoreBuilder::new().unwrap();
+            for c in &trusted {
+                xb.add_cert(c.clone()).unwrap();
+            }
+            let store = xb.build();
+            *report = JarReport::default();
+            match verify_jar_with_report(jar_file, store.as_ref(), &trusted, &VerifyPolicy::default(), report) {
+                Err(VerificationError { cert: Some(cert), .. }) if !trusted.contains(&cert) => trusted.push(cert),
+                r => return r,
+            }
+        }
+    }
+
+    #[test]
+    fn test_manifest_sections() {
+        let data = b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\nName: a.class\r\nSHA-256-Digest: AAA=\r\n\r\n\r\nName: very/long/\r\n path/b.class\r\nSHA-256-Digest: BBB=\r\nSHA1-Digest: CCC=";
+        let m = Manifest::parse("MANIFEST.MF", data.as_slice()).unwrap();
+        assert_eq!(b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\n", m.main_section_bytes());
+        assert_eq!(Some(b"Name: a.class\r\nSHA-256-Digest: AAA=\r\n\r\n".as_slice()), m.section_bytes("a.class"));
+        // the last section need not end with a blank line
+        assert_eq!(
+            Some(b"Name: very/long/\r\n path/b.class\r\nSHA-256-Digest: BBB=\r\nSHA1-Digest: CCC=".as_slice()),
+            m.section_bytes("very/long/path/b.class")
+        );
+        assert_eq!(
+            Some(&(String::from("SHA-256"), String::from("BBB="))),
+            m.name_digests.get("very/long/path/b.class")
+        );
+        assert_eq!(2, m.main_attribs.len());
+    }
+
+    #[test]
+    fn test_unsigned_entries_fail() {
+        let mut report = JarReport::default();
+        let e = verify_trusting_signers("test-resources/injected-unsigned-class.jar", &mut report).unwrap_err();
+        assert_eq!(
+            "unsigned entry com/sereen/catapult/Injected.class in test-resources/injected-unsigned-class.jar",
+            e.msg
+        );
+
+        // adding the injected class to the manifest doesn't sign it
+        let e = verify_trusting_signers("test-resources/injected-manifest-section.jar", &mut report).unwrap_err();
+        assert!(e.msg.starts_with("unsigned entry com/sereen/catapult/Injected.class"), "{}", e.msg);
+    }
+
+    #[test]
+    fn test_verify_sections_signed_by_different_signers() {
+        // signed by EC and then by RSA after adding added.txt, the manifest digest in EC.SF no longer matches
+        let mut report = JarReport::default();
+        verify_trusting_signers("test-resources/two-signers.jar", &mut report).unwrap();
+        assert_eq!(2, report.signers.len());
+        assert_eq!(5, report.entries_checked);
+    }
+
+    #[test]
+    fn test_verify_timestamped_after_expiry() {
+        // signed by a certificate of the test CA that expired minutes after signing
+        let ca = X509::from_pem(&std::fs::read("test-resources/test-ca.pem").unwrap()).unwrap();
+        let mut xb = X509StoreBuilder::new().unwrap();
+        xb.add_cert(ca).unwrap();
+        let store = xb.build();
+
+        let mut report = JarReport::default();
+        verify_jar_with_report("test-resources/tsa-signed-expired.jar", store.as_ref(), &[], &VerifyPolicy::default(), &mut report)
+            .unwrap();
+        let signer = &report.signers[0];
+        assert!(signer.signing_time.is_some());
+        assert!(report.warnings.is_empty());
+
+        let e = verify_jar("test-resources/expired-no-timestamp.jar", store.as_ref(), &[], &VerifyPolicy::default()).unwrap_err();
+        assert!(e.msg.contains("expired"), "{}", e.msg);
+
+        // the certificate had already expired when the timestamp was made
+        let mut report = JarReport::default();
+        let e = verify_jar_with_report("test-resources/tsa-after-expiry.jar", store.as_ref(), &[], &VerifyPolicy::default(), &mut report)
+            .unwrap_err();
+        assert!(e.msg.contains("at the signing time"), "{}", e.msg);
+        assert!(report.signers[0].signing_time.is_some());
+
+        // the TSA's certificate is not trusted without the CA
+        let store = X509StoreBuilder::new().unwrap().build();
+        let mut report = JarReport::default();
+        assert!(verify_jar_with_report("test-resources/tsa-signed-expired.jar", store.as_ref(), &[], &VerifyPolicy::default(), &mut report).is_err());
+        assert_eq!(None, report.signers[0].signing_time);
+        assert!(report.warnings[0].starts_with("invalid timestamp of META-INF/SIGNER.SF"), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn test_timestamp_requires_a_tsa_certificate() {
+        // the timestamp of tsa-signed-expired.jar signed again by other certificates of the test CA,
+        // tsa-resigned.jar by the same TSA certificate to show that signing it again keeps it valid
+        let ca = X509::from_pem(&std::fs::read("test-resources/test-ca.pem").unwrap()).unwrap();
+        let mut xb = X509StoreBuilder::new().unwrap();
+        xb.add_cert(ca).unwrap();
+        let store = xb.build();
+
+        let mut report = JarReport::default();
+        verify_jar_with_report("test-resources/tsa-resigned.jar", store.as_ref(), &[], &VerifyPolicy::default(), &mut report)
+            .unwrap();
+        assert!(report.signers[0].signing_time.is_some());
+
+        for (jar, reason) in [
+            ("test-resources/tsa-no-eku.jar", "unsuitable certificate purpose"),
+            ("test-resources/tsa-eku-not-critical.jar", "unsuitable certificate purpose"),
+            ("test-resources/tsa-issued-after-timestamp.jar", "outside the validity of the TSA certificate"),
+        ] {
+            let mut report = JarReport::default();
+            assert!(verify_jar_with_report(jar, store.as_ref(), &[], &VerifyPolicy::default(), &mut report).is_err(), "{}", jar);
+            assert_eq!(None, report.signers[0].signing_time, "{}", jar);
+            assert!(report.warnings[0].contains(reason), "{}: {:?}", jar, report.warnings);
+        }
+    }
+
+    fn crl_test_store() -> X509Store {
+        let ca = X509::from_pem(&std::fs::read("test-resources/crl-ca.pem").unwrap()).unwrap();
+        let mut xb = X509StoreBuilder::new().unwrap();
+        xb.add_cert(ca).unwrap();
+        xb.build()
+    }
+
+    /// a single test as the certificates point to the same distribution point
+    #[test]
+    fn test_revocation_check() {
+        let store = crl_test_store();
+        let good = "test-resources/crl-good-signed.jar";
+        let revoked = "test-resources/crl-revoked-signed.jar";
+        verify_jar(revoked, store.as_ref(), &[], &VerifyPolicy::default()).unwrap();
+
+        let cache_dir = std::env::temp_dir().join(format!("ballista-test-crls-{}", std::process::id()));
+        let local = VerifyPolicy {
+            revocation: RevocationCheck::HardFail,
+            crl_dir: Some(PathBuf::from("test-resources/crl")),
+            crl_cache_dir: Some(cache_dir.clone()),
+            ..Default::default()
+        };
+        verify_jar(good, store.as_ref(), &[], &local).unwrap();
+        let e = verify_jar(revoked, store.as_ref(), &[], &local).unwrap_err();
+        assert!(e.msg.ends_with("certificate revoked"), "{}", e.msg);
+        assert!(e.cert.is_none());
+
+        // the revocation of the explicitly trusted certificates is not checked
+        let cert = self_signed_cert(revoked);
+        verify_jar(revoked, store.as_ref(), &[cert], &local).unwrap();
+
+        // neither the CRL directory nor the distribution point of the certificates is reachable
+        let mut policy = VerifyPolicy {
+            revocation: RevocationCheck::SoftFail,
+            crl_dir: Some(PathBuf::from("test-resources/missing-crls")),
+            crl_cache_dir: Some(cache_dir.clone()),
+            ..Default::default()
+        };
+        let mut report = JarReport::default();
+        verify_jar_with_report(good, store.as_ref(), &[], &policy, &mut report).unwrap();
+        assert!(report.warnings[0].starts_with("failed to read the CRL directory"), "{:?}", report.warnings);
+        assert!(report.warnings[1].contains("unable to get certificate CRL"), "{:?}", report.warnings);
+        policy.revocation = RevocationCheck::HardFail;
+        let e = verify_jar(good, store.as_ref(), &[], &policy).unwrap_err();
+        assert!(e.msg.starts_with("failed to read the CRL directory"), "{}", e.msg);
+        policy.crl_dir = None;
+        assert!(verify_jar(good, store.as_ref(), &[], &policy).is_err());
+
+        // a fresh CRL of the distribution point in the cache is used without downloading it
+        let dp = "http://127.0.0.1:18931/ca.crl";
+        let cached = fetch_crl(&serve_crl_once(), &cache_dir).unwrap();
+        std::fs::rename(cached, cache_dir.join(format!("{}.pem", hex::encode(Sha256::digest(dp.as_bytes()))))).unwrap();
+        verify_jar(good, store.as_ref(), &[], &policy).unwrap();
+        let e = verify_jar(revoked, store.as_ref(), &[], &policy).unwrap_err();
+        assert!(e.msg.ends_with("certificate revoked"), "{}", e.msg);
+        assert_eq!(1, std::fs::read_dir(&cache_dir).unwrap().count());
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    /// serves test-resources/crl/ca.crl to a single request on a port chosen by the OS, returns its URL
+    fn serve_crl_once() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ca.crl", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let crl = std::fs::read("test-resources/crl/ca.crl").unwrap();
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", crl.len());
+            std::io::Write::write_all(&mut stream, header.as_bytes()).unwrap();
+            std::io::Write::write_all(&mut stream, &crl).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn test_fetch_crl() {
+        let cache_dir = std::env::temp_dir().join(format!("ballista-test-{}", uuid::Uuid::new_v4()));
+        let url = serve_crl_once();
+        let cached = fetch_crl(&url, &cache_dir).unwrap();
+        let crl = X509Crl::from_pem(&std::fs::read(&cached).unwrap()).unwrap();
+        assert_eq!(1, crl.get_revoked().map_or(0, |r| r.len()));
+        // the server is gone, the cached copy is still fresh
+        assert_eq!(cached, fetch_crl(&url, &cache_dir).unwrap());
+        assert!(fetch_crl("http://127.0.0.1:1/missing.crl", &cache_dir).is_err());
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_signer_chain() {
+        // the root CA comes first in the signature block, then the signer and its issuer
+        let jar_file = "test-resources/chain-signed.jar";
+        let mut sigblock = Vec::new();
+        let mut za = zip::ZipArchive::new(File::open(jar_file).unwrap()).unwrap();
+        za.by_name("META-INF/SIGNER.RSA").unwrap().read_to_end(&mut sigblock).unwrap();
+        let certs = extract_certs(&sigblock).unwrap();
+        let subjects: Vec<String> = certs.full_chain().iter().map(|c| format_name(c.subject_name())).collect();
+        assert_eq!(
+            vec![
+                "CN=Ballista Chain Test Signer",
+                "CN=Ballista Chain Test Intermediate CA",
+                "CN=Ballista Chain Test Root CA"
+            ],
+            subjects
+        );
+
+        let store = X509StoreBuilder::new().unwrap().build();
+        let e = verify_jar(jar_file, store.as_ref(), &[], &VerifyPolicy::default()).unwrap_err();
+        assert_eq!("CN=Ballista Chain Test Signer", format_name(e.cert.as_ref().unwrap().subject_name()));
+        assert_eq!(3, e.chain.len());
+        assert!(e.to_json().contains("\"chain\":["));
+
+        // only the root is trusted, the intermediate CA comes from the signature block
+        let root = X509::from_pem(&std::fs::read("test-resources/chain-root-ca.pem").unwrap()).unwrap();
+        let mut xb = X509StoreBuilder::new().unwrap();
+        xb.add_cert(root).unwrap();
+        let store = xb.build();
+        verify_jar(jar_file, store.as_ref(), &[], &VerifyPolicy::default()).unwrap();
+    }
 }
@@ -0,0 +1,185 @@
+// Copyright (c) Kiran Ayyagari. All rights reserved.
+// Copyright (c) Diridium Technologies Inc. All rights reserved.
+// Licensed under the MPL-2.0 License. See LICENSE file in the project root.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use openssl::hash::MessageDigest;
+use openssl::x509::store::X509StoreRef;
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::fsutil::write_atomic;
+use crate::verify::{JarReport, RevocationCheck, VerifyPolicy};
+
+/// the entries not used for this long are dropped when the cache is saved
+const MAX_UNUSED_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// The jars that passed the verification, keyed by the SHA-256 of their contents, so that the unchanged jars
+/// are not verified again on every launch. The whole cache is discarded when the trust store or the policy change.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VerifyCache {
+    /// the fingerprint of the trust store and the policy the jars were verified with
+    trust: String,
+    jars: HashMap<String, CachedResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResult {
+    warnings: Vec<String>,
+    /// seconds since the epoch after which the jar must be verified again, e.g. the signer's certificate expired
+    valid_until: Option<i64>,
+    last_used: i64,
+}
+
+impl VerifyCache {
+    /// An empty cache if the file doesn't exist, is invalid or was written for another trust store,
+    /// the latter is also returned as true, i.e. all the jars will be verified again.
+    pub fn load(path: &Path, trust: &str) -> (Self, bool) {
+        let cache: Option<VerifyCache> = File::open(path)
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok());
+        match cache {
+            Some(cache) if cache.trust == trust => (cache, false),
+            Some(_) => (VerifyCache::new(trust), true),
+            None => (VerifyCache::new(trust), false),
+        }
+    }
+
+    fn new(trust: &str) -> Self {
+        VerifyCache {
+            trust: trust.to_string(),
+            jars: HashMap::new(),
+        }
+    }
+
+    /// The warnings of the earlier verification of the jar, None if it has to be verified.
+    pub fn get(&mut self, sha256: &str) -> Option<Vec<String>> {
+        let now = now();
+        let r = self.jars.get_mut(sha256)?;
+        if r.valid_until.is_some_and(|t| t <= now) {
+            return None;
+        }
+        r.last_used = now;
+        Some(r.warnings.clone())
+    }
+
+    pub fn insert(&mut self, sha256: String, report: &JarReport) {
+        let r = CachedResult {
+            warnings: report.warnings.clone(),
+            valid_until: report.valid_until,
+            last_used: now(),
+        };
+        self.jars.insert(sha256, r);
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<(), Error> {
+        let oldest = now() - MAX_UNUSED_SECS;
+        self.jars.retain(|_, r| r.last_used >= oldest);
+        let val = serde_json::to_string(self)?;
+        write_atomic(path, val.as_bytes(), 0)
+    }
+}
+
+/// Whether the results can be cached, the revocation of a certificate is only known by checking again.
+pub fn is_cacheable(policy: &VerifyPolicy) -> bool {
+    policy.revocation == RevocationCheck::Off
+}
+
+/// SHA-256 of the certificates of the store, the ones trusted by the user and the policy.
+pub fn trust_fingerprint(cert_store: &X509StoreRef, trusted_certs: &[X509], policy: &VerifyPolicy) -> Result<String, Error> {
+    let mut fingerprints = Vec::new();
+    for c in cert_store.all_certificates().iter().chain(trusted_certs.iter().map(|c| c.as_ref())) {
+        fingerprints.push(c.digest(MessageDigest::sha256())?.to_vec());
+    }
+    fingerprints.sort_unstable();
+
+    let mut hasher = Sha256::new();
+    for f in fingerprints {
+        hasher.update(f);
+    }
+    hasher.update(serde_json::to_vec(policy)?);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn jar_sha256(path: &Path) -> Result<String, Error> {
+    let mut f = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::x509::store::X509StoreBuilder;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_verify_cache() {
+        let dir = std::env::temp_dir().join(format!("ballista-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("verified-jars.json");
+
+        let store = X509StoreBuilder::new().unwrap().build();
+        let policy = VerifyPolicy::default();
+        let trust = trust_fingerprint(store.as_ref(), &[], &policy).unwrap();
+        assert_eq!(trust, trust_fingerprint(store.as_ref(), &[], &policy).unwrap());
+
+        let jar = jar_sha256(Path::new("test-resources/ec-signed.jar")).unwrap();
+        let (mut cache, discarded) = VerifyCache::load(&path, &trust);
+        assert!(!discarded);
+        assert_eq!(None, cache.get(&jar));
+        let report = JarReport {
+            warnings: vec![String::from("weak")],
+            ..Default::default()
+        };
+        cache.insert(jar.clone(), &report);
+        let expired = JarReport {
+            valid_until: Some(now() - 1),
+            ..Default::default()
+        };
+        cache.insert(String::from("expired"), &expired);
+        cache.save(&path).unwrap();
+
+        let (mut cache, discarded) = VerifyCache::load(&path, &trust);
+        assert!(!discarded);
+        assert_eq!(Some(vec![String::from("weak")]), cache.get(&jar));
+        assert_eq!(None, cache.get("expired"));
+
+        // trusting a certificate or changing the policy discards the cache
+        let cert = X509::from_pem(&std::fs::read("test-resources/test-ca.pem").unwrap()).unwrap();
+        let other = trust_fingerprint(store.as_ref(), &[cert], &policy).unwrap();
+        assert_ne!(trust, other);
+        let (mut cache, discarded) = VerifyCache::load(&path, &other);
+        assert!(discarded);
+        assert_eq!(None, cache.get(&jar));
+        let weak = VerifyPolicy {
+            allow_weak_digests: true,
+            ..Default::default()
+        };
+        assert_ne!(trust, trust_fingerprint(store.as_ref(), &[], &weak).unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
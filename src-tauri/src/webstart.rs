@@ -2,13 +2,16 @@
 // Copyright (c) Diridium Technologies Inc. All rights reserved.
 // Licensed under the MPL-2.0 License. See LICENSE file in the project root.
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::SystemTime;
 
 use anyhow::Error;
@@ -21,9 +24,17 @@ use rustc_hash::FxHashMap;
 use sha2::{Digest, Sha256};
 use tauri::ipc::Channel;
 
+use crate::argpolicy::{ArgPolicy, ArgSource, DroppedArg, JAVA_OPTIONS_ENV_VARS};
 use crate::connection::ConnectionEntry;
 use crate::errors::VerificationError;
-use crate::verify::verify_jar;
+use crate::verify::{verify_jar_with_report, JarReport, VerifyPolicy};
+use crate::verifycache::{is_cacheable, jar_sha256, trust_fingerprint, VerifyCache};
+
+/// the directory inside a version's jar directory for the extracted native libraries
+const NATIVE_DIR: &str = "native";
+
+/// the jars are verified by at most these many threads
+const MAX_VERIFY_WORKERS: usize = 8;
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -32,10 +43,23 @@ pub struct WebstartFile {
     main_class: String,
     args: Vec<String>,
     j2ses: Option<Vec<J2se>>,
+    /// the <property> elements that passed sanitize_properties, in the order they were declared
+    properties: Vec<(String, String)>,
     jar_dir: PathBuf,
+    /// the <nativelib> jars of the JNLP and its extensions
+    native_jars: Vec<PathBuf>,
     loaded_at: SystemTime,
 }
 
+/// What the matching <resources> elements of a JNLP and of its extensions declare besides the jars.
+#[derive(Default)]
+struct Declarations {
+    j2ses: Vec<J2se>,
+    /// not sanitized yet
+    properties: Vec<(String, String)>,
+    native_jars: Vec<PathBuf>,
+}
+
 /// from jnlp -> resources -> j2se
 #[derive(Debug)]
 pub struct J2se {
@@ -67,12 +91,16 @@ impl WebStartCache {
         }
         None
     }
+
+    pub fn clear(&self) {
+        self.cache.lock().expect("webstart cache lock poisoned").clear();
+    }
 }
 
 impl WebstartFile {
-    pub fn load(base_url: &str, cache_dir: &PathBuf, donotcache: bool, conn_id: &str, conn_name: &str, on_progress: &Channel<serde_json::Value>) -> Result<WebstartFile, Error> {
-        let (base_url, _host) = normalize_url(base_url)?;
-        let webstart = format!("{}/webstart.jnlp", base_url); // base_url will never contain a / at the end after normalization
+    /// The address is either the server's address, whose webstart.jnlp is loaded, or the location of a JNLP file,
+    /// see is_jnlp_location. The jars are resolved against the JNLP's codebase.
+    pub fn load(address: &str, cache_dir: &Path, donotcache: bool, conn_id: &str, on_progress: &Channel<serde_json::Value>) -> Result<WebstartFile, Error> {
         let _ = on_progress.send(serde_json::json!({"message": "Fetching server configuration..."}));
         let cb = ClientBuilder::default()
             // in certain network environments client is failing with error message "connection closed before message completed"
@@ -82,11 +110,17 @@ impl WebstartFile {
             .danger_accept_invalid_certs(true);
         let client = cb.build()?;
 
-        let r = client.get(&webstart).send()?;
-        let data = r.text()?;
+        let (jnlp_url, data) = if is_jnlp_location(address) {
+            fetch_jnlp(&client, address)?
+        } else {
+            let (base_url, _host) = normalize_url(address)?;
+            // base_url will never contain a / at the end after normalization
+            fetch_jnlp(&client, &format!("{}/webstart.jnlp", base_url))?
+        };
         let doc = roxmltree::Document::parse(&data)?;
 
         let root = doc.root();
+        let codebase = get_codebase(&jnlp_url, get_node(&root, "jnlp").and_then(|n| n.attribute("codebase")))?;
         let main_class_node = get_node(&root, "application-desc").ok_or(Error::msg(
             "Got something from MC that was not an application-desc node in a JNLP XML",
         ))?;
@@ -96,8 +130,6 @@ impl WebstartFile {
             .to_string();
         let args = get_client_args(&main_class_node);
 
-        let resources_node = get_node(&root, "resources");
-
         let mut version = "default".to_string();
         if let Some(jnlp_node) = get_node(&root, "jnlp") {
             if let Some(v) = jnlp_node.attribute("version") {
@@ -106,14 +138,7 @@ impl WebstartFile {
             }
         }
 
-        let sanitized_name = conn_name
-            .to_lowercase()
-            .chars()
-            .map(|c| if c.is_alphanumeric() { c } else { '-' })
-            .collect::<String>();
-        let id_prefix = &conn_id[..conn_id.len().min(8)];
-        let cache_folder = format!("{}_{}", sanitized_name, id_prefix);
-        let jar_dir = cache_dir.join(cache_folder).join(&version);
+        let jar_dir = connection_cache_dir(cache_dir, conn_id).join(&version);
         if donotcache && jar_dir.exists() {
             println!("removing directory {:?}", jar_dir);
             std::fs::remove_dir_all(&jar_dir)?;
@@ -125,26 +150,29 @@ impl WebstartFile {
             std::fs::create_dir_all(dir_path)?;
         }
 
-        let mut j2ses = None;
-        if let Some(resources_node) = resources_node {
-            j2ses = get_j2ses(&resources_node);
-            download_jars(&resources_node, &client, dir_path, &base_url, on_progress)?;
-        }
+        let mut declarations = Declarations::default();
+        download_jars(&root, &client, dir_path, &codebase, on_progress, &mut declarations)?;
 
         let loaded_at = SystemTime::now();
         let ws = WebstartFile {
-            url: base_url.to_string(),
+            url: address.to_string(),
             main_class,
             jar_dir,
+            // the native libraries are extracted by run, after the jars were verified
+            native_jars: declarations.native_jars,
             args,
             loaded_at,
-            j2ses,
+            j2ses: (!declarations.j2ses.is_empty()).then_some(declarations.j2ses),
+            properties: sanitize_properties(declarations.properties),
         };
 
         Ok(ws)
     }
 
-    pub fn run(&self, ce: Arc<ConnectionEntry>, console_jar: Option<PathBuf>) -> Result<(), Error> {
+    /// Launches the administrator, the JVM arguments dropped by the policy are returned.
+    pub fn run(&self, ce: Arc<ConnectionEntry>, console_jar: Option<PathBuf>, policy: &ArgPolicy) -> Result<Vec<DroppedArg>, Error> {
+        let native_dir = prepare_native_dir(&self.jar_dir, &self.native_jars)?;
+        let mut dropped = Vec::new();
         let itr = self.jar_dir.read_dir()?;
         let mut classpath = String::with_capacity(1152);
         let mut classpath_suffix = String::with_capacity(1024);
@@ -198,7 +226,7 @@ impl WebstartFile {
                 // this will be ignored by java version <= 1.8
                 if va.version.contains("1.9") {
                     if let Some(java_vm_args) = &va.java_vm_args {
-                        let filtered = sanitize_vm_args(java_vm_args);
+                        let filtered = policy.filter_options(java_vm_args, ArgSource::Jnlp, &mut dropped);
                         if !filtered.is_empty() {
                             println!("setting JDK_JAVA_OPTIONS environment variable with the java-vm-args given for version {} in JNLP file", va.version);
                             cmd.env("JDK_JAVA_OPTIONS", &filtered);
@@ -208,14 +236,44 @@ impl WebstartFile {
             }
         }
 
+        // per-connection settings are applied after the JNLP provided ones so that they take precedence:
+        // environment variables, then -Xmx, then -D system properties and finally the free-text java_args
+        for (k, v) in &ce.env_vars {
+            // the JVM reads arguments from these too
+            if JAVA_OPTIONS_ENV_VARS.iter().any(|name| k.eq_ignore_ascii_case(name)) {
+                cmd.env(k, policy.filter_options(v, ArgSource::Connection, &mut dropped));
+            } else {
+                cmd.env(k, v);
+            }
+        }
+
+        if let Some(working_dir) = ce.working_dir.as_deref() {
+            let working_dir = working_dir.trim();
+            if !working_dir.is_empty() {
+                if !Path::new(working_dir).is_dir() {
+                    return Err(Error::msg(format!("working directory {} does not exist", working_dir)));
+                }
+                cmd.current_dir(working_dir);
+            }
+        }
+
         let heap = ce.heap_size.trim();
         if !heap.is_empty() {
-            cmd.arg(format!("-Xmx{}", heap));
+            cmd.args(policy.filter(&[format!("-Xmx{}", heap)], ArgSource::Connection, &mut dropped));
+        }
+
+        if let Some(native_dir) = native_dir.as_deref().and_then(Path::to_str) {
+            cmd.arg(format!("-Djava.library.path={}", native_dir));
         }
 
+        // the connection's own system properties come later and override those of the JNLP
+        let jnlp_properties: Vec<String> = self.properties.iter().map(|(k, v)| format!("-D{}={}", k, v)).collect();
+        cmd.args(policy.filter(&jnlp_properties, ArgSource::Jnlp, &mut dropped));
+        cmd.args(policy.filter(&get_system_property_args(&ce.system_properties), ArgSource::Connection, &mut dropped));
+
         if let Some(args) = ce.java_args.as_deref() {
-            // Should probably do some sanitization here...
-            cmd.args(args.trim().lines());
+            let args: Vec<&str> = args.trim().lines().filter(|l| !l.trim().is_empty()).collect();
+            cmd.args(policy.filter(&args, ArgSource::Connection, &mut dropped));
         }
 
         cmd.arg("-cp")
@@ -290,88 +348,211 @@ impl WebstartFile {
             cmd.spawn()?;
         }
 
-        Ok(())
+        Ok(dropped)
     }
 
-    pub fn verify(&self, cert_store: &X509StoreRef, trusted_certs: &[X509]) -> Result<(), VerificationError> {
+    /// Verifies all the jars in parallel, the warnings about the weak algorithms that were accepted are returned.
+    /// The jars found in the cache at cache_path are not verified again.
+    pub fn verify(
+        &self,
+        cert_store: &X509StoreRef,
+        trusted_certs: &[X509],
+        policy: &VerifyPolicy,
+        cache_path: Option<&Path>,
+    ) -> Result<Vec<String>, VerificationError> {
         let mut jar_files = Vec::with_capacity(128);
         let itr = self
             .jar_dir
             .read_dir()
             .map_err(|e| VerificationError {
                 cert: None,
+                chain: Vec::new(),
                 msg: format!("failed to read jar files directory: {}", e),
             })?;
         for e in itr {
             let e = e.map_err(|e| VerificationError {
                 cert: None,
+                chain: Vec::new(),
                 msg: format!("failed to list directory entry: {}", e),
             })?;
             let file_path = e.path();
+            // the extracted native libraries come from the verified jars
+            if file_path.is_dir() {
+                continue;
+            }
             jar_files.push(file_path);
         }
 
         jar_files.sort_unstable();
-        println!("{:?}", jar_files);
 
-        for jf in jar_files {
-            let file_path = jf.to_str().ok_or_else(|| VerificationError {
-                cert: None,
-                msg: format!("jar file path is not valid UTF-8: {:?}", jf),
-            })?;
-            verify_jar(file_path, cert_store, trusted_certs)?;
+        let cache = match cache_path.filter(|_| is_cacheable(policy)) {
+            Some(path) => {
+                let trust = trust_fingerprint(cert_store, trusted_certs, policy)?;
+                let (cache, discarded) = VerifyCache::load(path, &trust);
+                if discarded {
+                    println!("the trust store or the verification policy changed, verifying all the jars again");
+                }
+                Some((path, Mutex::new(cache)))
+            }
+            None => None,
+        };
+
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_VERIFY_WORKERS).min(jar_files.len());
+        let mut results: Vec<Option<Result<Vec<String>, VerificationError>>> = Vec::new();
+        results.resize_with(jar_files.len(), || None);
+        let results = Mutex::new(results);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= jar_files.len() || failed.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let r = verify_cached(&jar_files[i], cert_store, trusted_certs, policy, cache.as_ref().map(|(_, c)| c));
+                    if r.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    results.lock().expect("verification results lock poisoned")[i] = Some(r);
+                });
+            }
+        });
+
+        if let Some((path, cache)) = cache {
+            let mut cache = cache.into_inner().expect("verified jars cache lock poisoned");
+            if let Err(e) = cache.save(path) {
+                println!("failed to save the verified jars cache {:?}: {}", path, e);
+            }
         }
-        Ok(())
+
+        // the first failure in the order of the jars, the jars after it may not have been verified
+        let mut warnings = Vec::new();
+        for r in results.into_inner().expect("verification results lock poisoned").into_iter().flatten() {
+            warnings.extend(r?);
+        }
+        Ok(warnings)
+    }
+}
+
+/// Verifies the jar unless the cache has it, the jars that pass are added to the cache.
+fn verify_cached(
+    jar_file: &Path,
+    cert_store: &X509StoreRef,
+    trusted_certs: &[X509],
+    policy: &VerifyPolicy,
+    cache: Option<&Mutex<VerifyCache>>,
+) -> Result<Vec<String>, VerificationError> {
+    let file_path = jar_file.to_str().ok_or_else(|| VerificationError {
+        cert: None,
+        chain: Vec::new(),
+        msg: format!("jar file path is not valid UTF-8: {:?}", jar_file),
+    })?;
+    let sha256 = match cache {
+        Some(cache) => {
+            let sha256 = jar_sha256(jar_file)?;
+            if let Some(warnings) = cache.lock().expect("verified jars cache lock poisoned").get(&sha256) {
+                println!("{} was already verified", file_path);
+                return Ok(warnings);
+            }
+            Some(sha256)
+        }
+        None => None,
+    };
+
+    let mut report = JarReport::default();
+    verify_jar_with_report(file_path, cert_store, trusted_certs, policy, &mut report)?;
+    if let (Some(cache), Some(sha256)) = (cache, sha256) {
+        cache.lock().expect("verified jars cache lock poisoned").insert(sha256, &report);
+    }
+    Ok(report.warnings)
+}
+
+#[derive(Debug, PartialEq)]
+enum ResourceKind {
+    Jar,
+    /// a jar with native libraries at its root
+    NativeLib,
+    /// another JNLP file with more resources
+    Extension,
+}
+
+/// a jar, nativelib or an extension JNLP of a <resources> element
+#[derive(Debug)]
+struct Resource<'a> {
+    url: Url,
+    kind: ResourceKind,
+    sha256: Option<&'a str>,
+}
+
+/// The jars, nativelibs and extensions of the <resources> element with their hrefs resolved against the codebase per RFC 3986.
+fn get_resources<'a>(resources_node: &Node<'a, 'a>, codebase: &Url) -> Result<Vec<Resource<'a>>, Error> {
+    let mut resources = Vec::new();
+    for n in resources_node.children() {
+        let kind = match n.tag_name().name() {
+            "jar" => ResourceKind::Jar,
+            "nativelib" => ResourceKind::NativeLib,
+            "extension" => ResourceKind::Extension,
+            _ => continue,
+        };
+
+        let href = match n.attribute("href") {
+            Some(h) => h.trim(),
+            None => continue,
+        };
+        let url = codebase
+            .join(href)
+            .map_err(|e| Error::msg(format!("invalid href {} for codebase {}: {}", href, codebase, e)))?;
+        resources.push(Resource {
+            url,
+            kind,
+            sha256: n.attribute("sha256"),
+        });
     }
+    Ok(resources)
 }
 
+/// Downloads the jars and nativelibs of all the <resources> elements matching this OS and architecture,
+/// including those of the extensions. Their j2se elements, properties and nativelib jars are added to
+/// declarations, those of the JNLP before those of its extensions.
 fn download_jars(
-    resources_node: &Node,
+    root: &Node,
     client: &Client,
     dir_path: &Path,
-    base_url: &str,
+    codebase: &Url,
     on_progress: &Channel<serde_json::Value>,
+    declarations: &mut Declarations,
 ) -> Result<(), Error> {
+    let (os, arch) = current_os_arch();
     let mut counter = 0usize;
-    for n in resources_node.children() {
-        let jar = n.has_tag_name("jar");
-        let extension = n.has_tag_name("extension");
-
-        if !jar && !extension {
-            continue;
-        }
-
-        let href = match n.attribute("href") {
-            Some(h) => h,
-            None => continue,
-        };
-        let hash_in_jnlp = n.attribute("sha256");
-        let url = format!("{}/{}", base_url, href);
+    for resources_node in collect_declarations(root, &[os.as_str()], &arch, declarations) {
+        for r in get_resources(&resources_node, codebase)? {
+            if r.kind == ResourceKind::Extension {
+                let data = client.get(r.url.clone()).send()?.error_for_status()?.text()?;
+                let doc = roxmltree::Document::parse(&data)?;
+                let ext_root = doc.root();
+                // the hrefs of an extension are relative to its own codebase, or its own location
+                let ext_codebase = get_codebase(&r.url, get_node(&ext_root, "jnlp").and_then(|n| n.attribute("codebase")))?;
+                download_jars(&ext_root, client, dir_path, &ext_codebase, on_progress, declarations)?;
+                continue;
+            }
 
-        if jar {
-            let file_name = get_file_name_from_path(href);
+            let file_name = get_file_name_from_url(&r.url)?;
             counter += 1;
             let jar_file_path = dir_path.join(file_name);
             let _ = on_progress.send(serde_json::json!({
                 "message": format!("Verifying cache file {}", file_name),
             }));
-            if has_file_changed(&jar_file_path, hash_in_jnlp)? {
+            if has_file_changed(&jar_file_path, r.sha256)? {
                 let _ = on_progress.send(serde_json::json!({
                     "message": format!("Downloading {} ({})", file_name, counter),
                 }));
-                let mut resp = client.get(url).send()?;
+                let mut resp = client.get(r.url.clone()).send()?.error_for_status()?;
                 let mut f = File::create(&jar_file_path)?;
                 resp.copy_to(&mut f)?;
             }
-        } else if extension {
-            let r = client.get(url).send()?;
-            let data = r.text()?;
-            let doc = roxmltree::Document::parse(&data)?;
-            let root = doc.root();
-            let resources_node = get_node(&root, "resources");
-            let ext_base_url = format!("{}/webstart/extensions", base_url);
-            if let Some(resources_node) = resources_node {
-                download_jars(&resources_node, client, dir_path, &ext_base_url, on_progress)?;
+            if r.kind == ResourceKind::NativeLib {
+                declarations.native_jars.push(jar_file_path);
             }
         }
     }
@@ -379,34 +560,290 @@ fn download_jars(
     Ok(())
 }
 
-/// Filter JNLP java-vm-args to block flags that could execute arbitrary code.
-fn sanitize_vm_args(args: &str) -> String {
-    let dangerous_prefixes: &[&str] = &[
-        "-javaagent:",
-        "-agentpath:",
-        "-agentlib:",
-        "-xbootclasspath",
-        "-xx:onoutofmemoryerror",
-        "-xx:onoutofmemoryerror=",
-        "-xx:onerror",
-        "-xx:onerror=",
-    ];
-
-    args.split_whitespace()
-        .filter(|arg| {
-            let lower = arg.to_lowercase();
-            let dominated = dangerous_prefixes.iter().any(|p| lower.starts_with(p));
-            if dominated {
-                println!("sanitize_vm_args: dropping dangerous flag: {}", arg);
+/// Adds the j2se elements and properties of the <resources> elements matching os and arch to declarations,
+/// the matching elements are returned.
+fn collect_declarations<'a>(
+    root: &Node<'a, 'a>,
+    os: &[&str],
+    arch: &[&str],
+    declarations: &mut Declarations,
+) -> Vec<Node<'a, 'a>> {
+    let matching = get_matching_resources(root, os, arch);
+    declarations.j2ses.extend(get_j2ses(&matching).unwrap_or_default());
+    declarations.properties.extend(get_properties(&matching));
+    matching
+}
+
+/// The OS name as reported by Java's os.name and the architecture names of os.arch, the first
+/// of them is the value reported on this machine, the others are the common aliases.
+fn current_os_arch() -> (String, Vec<&'static str>) {
+    let os = match std::env::consts::OS {
+        "windows" => windows_os_name(),
+        "macos" => String::from("Mac OS X"),
+        "linux" => String::from("Linux"),
+        other => other.to_string(),
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => vec!["amd64", "x86_64", "x64"],
+        "aarch64" => vec!["aarch64", "arm64"],
+        "x86" => vec!["x86", "i386", "i686"],
+        other => vec![other],
+    };
+    (os, arch)
+}
+
+/// The <resources> elements whose os and arch attributes match, per the JNLP spec these are
+/// space separated lists of values each of which is a prefix of the os.name or os.arch.
+/// Those of the <jnlp> element count and those nested in the selected <j2se>, i.e. the first one of the
+/// matching elements, the <resources> of the other <j2se> elements are meant for JREs that aren't used.
+fn get_matching_resources<'a>(root: &Node<'a, 'a>, os: &[&str], arch: &[&str]) -> Vec<Node<'a, 'a>> {
+    let matches = |n: &Node| {
+        n.has_tag_name("resources")
+            && attribute_matches(n.attribute("os"), os)
+            && attribute_matches(n.attribute("arch"), arch)
+    };
+    let Some(jnlp) = root.descendants().find(|n| n.has_tag_name("jnlp")) else {
+        return Vec::new();
+    };
+    let mut matching: Vec<Node<'a, 'a>> = jnlp.children().filter(matches).collect();
+    let selected = matching.iter().flat_map(|r| r.children()).find(|n| n.has_tag_name("j2se"));
+    if let Some(j2se) = selected {
+        matching.extend(j2se.children().filter(matches));
+    }
+    matching
+}
+
+fn attribute_matches(attr: Option<&str>, current: &[&str]) -> bool {
+    let values = attribute_values(attr.unwrap_or_default());
+    if values.is_empty() {
+        return true;
+    }
+    values.iter().any(|value| {
+        let value = value.to_ascii_lowercase();
+        current.iter().any(|c| c.to_ascii_lowercase().starts_with(&value))
+    })
+}
+
+/// Splits an os or arch attribute into its values, a space within a value is escaped, e.g. "Mac\ OS\ X".
+fn attribute_values(attr: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut chars = attr.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            value.extend(chars.next());
+        } else if c.is_whitespace() {
+            if !value.is_empty() {
+                values.push(std::mem::take(&mut value));
             }
-            !dominated
+        } else {
+            value.push(c);
+        }
+    }
+    if !value.is_empty() {
+        values.push(value);
+    }
+    values
+}
+
+#[cfg(windows)]
+fn windows_os_name() -> String {
+    /// OSVERSIONINFOEXW
+    #[repr(C)]
+    struct OsVersionInfo {
+        size: u32,
+        major: u32,
+        minor: u32,
+        build: u32,
+        platform_id: u32,
+        csd_version: [u16; 128],
+        service_pack_major: u16,
+        service_pack_minor: u16,
+        suite_mask: u16,
+        product_type: u8,
+        reserved: u8,
+    }
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn RtlGetVersion(info: *mut OsVersionInfo) -> i32;
+    }
+    const VER_NT_WORKSTATION: u8 = 1;
+
+    // SAFETY: OsVersionInfo is plain data and its size field is set as RtlGetVersion requires
+    let mut info: OsVersionInfo = unsafe { std::mem::zeroed() };
+    info.size = std::mem::size_of::<OsVersionInfo>() as u32;
+    if unsafe { RtlGetVersion(&mut info) } != 0 {
+        return String::from("Windows");
+    }
+    windows_name(info.major, info.minor, info.build, info.product_type == VER_NT_WORKSTATION)
+}
+
+#[cfg(not(windows))]
+fn windows_os_name() -> String {
+    String::from("Windows")
+}
+
+/// The os.name Java derives from the Windows version, e.g. "Windows 11" or "Windows Server 2022".
+#[cfg_attr(not(windows), allow(dead_code))]
+fn windows_name(major: u32, minor: u32, build: u32, workstation: bool) -> String {
+    let name = match (major, minor, workstation) {
+        (5, 1, _) => "Windows XP",
+        (6, 0, true) => "Windows Vista",
+        (6, 0, false) => "Windows Server 2008",
+        (6, 1, true) => "Windows 7",
+        (6, 1, false) => "Windows Server 2008 R2",
+        (6, 2, true) => "Windows 8",
+        (6, 2, false) => "Windows Server 2012",
+        (6, 3, true) => "Windows 8.1",
+        (6, 3, false) => "Windows Server 2012 R2",
+        (10, 0, true) if build >= 22000 => "Windows 11",
+        (10, 0, true) => "Windows 10",
+        (10, 0, false) if build >= 26100 => "Windows Server 2025",
+        (10, 0, false) if build >= 20348 => "Windows Server 2022",
+        (10, 0, false) if build >= 17763 => "Windows Server 2019",
+        (10, 0, false) => "Windows Server 2016",
+        _ => "Windows NT (unknown)",
+    };
+    name.to_string()
+}
+
+/// Extracts the native libraries only when launching, i.e. after the jars were verified. The directory is named
+/// after the digest of the native jars, so the same jars are extracted once and the libraries loaded by a running
+/// administrator are never overwritten, Windows doesn't allow that. Returns None if there are no native jars.
+fn prepare_native_dir(jar_dir: &Path, native_jars: &[PathBuf]) -> Result<Option<PathBuf>, Error> {
+    if native_jars.is_empty() {
+        return Ok(None);
+    }
+    let mut hasher = Sha256::new();
+    for jar in native_jars {
+        hasher.update(jar_sha256(jar)?.as_bytes());
+    }
+    let digest = hex::encode(hasher.finalize());
+    let parent = jar_dir.join(NATIVE_DIR);
+    let native_dir = parent.join(&digest[..16]);
+    if native_dir.is_dir() {
+        return Ok(Some(native_dir));
+    }
+
+    // extracted next to it and renamed, an interrupted extraction is never used
+    let partial = parent.join(format!("{}.partial-{}", &digest[..16], uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&partial)?;
+    let extracted = native_jars.iter().try_for_each(|jar| extract_native_libs(jar, &partial));
+    let extracted = extracted.and_then(|_| std::fs::rename(&partial, &native_dir).map_err(Error::new));
+    if let Err(e) = extracted {
+        let _ = std::fs::remove_dir_all(&partial);
+        // unless another launch extracted the same jars in the meantime
+        if !native_dir.is_dir() {
+            return Err(e);
+        }
+    }
+
+    // the libraries of the older jars, those still loaded by a running administrator are removed on a later launch
+    for e in parent.read_dir()?.flatten() {
+        let path = e.path();
+        let in_progress = e.file_name().to_string_lossy().contains(".partial-");
+        if path != native_dir && !in_progress && std::fs::remove_dir_all(&path).is_err() {
+            println!("unable to remove the native libraries {:?}, they may still be in use", path);
+        }
+    }
+    Ok(Some(native_dir))
+}
+
+/// Extracts the native libraries at the root of the jar into native_dir, the other entries are ignored.
+fn extract_native_libs(jar: &Path, native_dir: &Path) -> Result<(), Error> {
+    let mut za = zip::ZipArchive::new(File::open(jar)?)?;
+    for i in 0..za.len() {
+        let mut entry = za.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        // enclosed_name rejects absolute paths and .. components
+        let Some(name) = entry.enclosed_name() else {
+            println!("skipping entry {} of {:?} with an unsafe path", entry.name(), jar);
+            continue;
+        };
+        if name.components().count() != 1 {
+            continue;
+        }
+        let mut f = File::create(native_dir.join(name))?;
+        std::io::copy(&mut entry, &mut f)?;
+    }
+    Ok(())
+}
+
+/// JNLP properties that may be set even though their prefix is in DENIED_PROPERTY_PREFIXES.
+const ALLOWED_PROPERTIES: &[&str] = &[
+    "java.awt.headless",
+    "java.net.preferIPv4Stack",
+    "java.net.preferIPv6Addresses",
+    "java.util.Arrays.useLegacyMergeSort",
+    "jdk.gtk.version",
+    "sun.awt.disableMixing",
+    "sun.java2d.d3d",
+    "sun.java2d.dpiaware",
+    "sun.java2d.noddraw",
+    "sun.java2d.opengl",
+    "sun.java2d.uiScale",
+    "sun.java2d.xrender",
+];
+
+/// JNLP properties that can change how classes are loaded, which code is trusted or the TLS settings of the client.
+const DENIED_PROPERTY_PREFIXES: &[&str] = &[
+    "java.",
+    "javax.net.ssl.",
+    "jdk.",
+    "sun.",
+    "com.sun.",
+    "javaws.",
+    "jnlp.javaws",
+    // log4j loads its configuration, and with it appenders and lookups, from these
+    "log4j.configuration",
+    "log4j2.",
+];
+
+/// Filter JNLP <property> elements, the names in ALLOWED_PROPERTIES and those not matching
+/// DENIED_PROPERTY_PREFIXES are passed as system properties, e.g. the ones custom server builds use.
+fn sanitize_properties(props: Vec<(String, String)>) -> Vec<(String, String)> {
+    props
+        .into_iter()
+        .filter(|(name, _)| {
+            let valid = !name.is_empty() && !name.contains(|c: char| c == '=' || c.is_whitespace() || c.is_control());
+            let allowed = valid
+                && (ALLOWED_PROPERTIES.contains(&name.as_str())
+                    || !DENIED_PROPERTY_PREFIXES.iter().any(|p| name.to_lowercase().starts_with(p)));
+            if !allowed {
+                println!("sanitize_properties: dropping JNLP property: {}", name);
+            }
+            allowed
+        })
+        .collect()
+}
+
+fn get_properties(resources: &[Node]) -> Vec<(String, String)> {
+    resources
+        .iter()
+        .flat_map(|r| r.children())
+        .filter(|n| n.has_tag_name("property"))
+        .filter_map(|n| {
+            let name = n.attribute("name")?.trim().to_string();
+            Some((name, n.attribute("value").unwrap_or_default().to_string()))
         })
-        .collect::<Vec<_>>()
-        .join(" ")
+        .collect()
 }
 
-fn get_file_name_from_path(p: &str) -> &str {
-    p.rsplit('/').next().unwrap_or(p)
+fn get_system_property_args(props: &BTreeMap<String, String>) -> Vec<String> {
+    props
+        .iter()
+        .filter(|(k, _)| !k.trim().is_empty())
+        .map(|(k, v)| format!("-D{}={}", k.trim(), v))
+        .collect()
+}
+
+fn get_file_name_from_url(url: &Url) -> Result<&str, Error> {
+    url.path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+        .ok_or_else(|| Error::msg(format!("no file name in {}", url)))
 }
 
 fn get_client_args(root: &Node) -> Vec<String> {
@@ -421,9 +858,9 @@ fn get_client_args(root: &Node) -> Vec<String> {
     args
 }
 
-fn get_j2ses(resources: &Node) -> Option<Vec<J2se>> {
+fn get_j2ses(resources: &[Node]) -> Option<Vec<J2se>> {
     let mut j2ses = Vec::new();
-    for n in resources.descendants() {
+    for n in resources.iter().flat_map(|r| r.children()) {
         if n.has_tag_name("j2se") {
             // only consider those that have java-vm-args and version
             if let Some(java_vm_args) = n.attribute("java-vm-args") {
@@ -453,7 +890,70 @@ fn get_node<'a>(root: &'a Node, tag_name: &str) -> Option<Node<'a, 'a>> {
     })
 }
 
-fn normalize_url(u: &str) -> Result<(String, String), Error> {
+/// true when the address points to a JNLP file, i.e. an http(s) or file URL or a local path ending with .jnlp
+/// The cache directory of a connection is named after its id so that renaming the connection keeps it.
+pub(crate) fn connection_cache_dir(cache_dir: &Path, conn_id: &str) -> PathBuf {
+    // ids are UUIDs, anything else must not escape the cache directory
+    let name: String = conn_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    cache_dir.join(name)
+}
+
+pub(crate) fn is_jnlp_location(address: &str) -> bool {
+    let path = match Url::parse(address) {
+        Ok(u) if matches!(u.scheme(), "http" | "https" | "file") => u.path().to_string(),
+        // also a Windows path like C:\\dir\\app.jnlp whose drive letter gets parsed as a scheme
+        _ => address.to_string(),
+    };
+    path.trim().to_ascii_lowercase().ends_with(".jnlp")
+}
+
+fn fetch_jnlp(client: &Client, location: &str) -> Result<(Url, String), Error> {
+    let location = location.trim();
+    match Url::parse(location) {
+        Ok(u) if matches!(u.scheme(), "http" | "https") => {
+            let data = client.get(u.clone()).send()?.error_for_status()?.text()?;
+            Ok((u, data))
+        }
+        Ok(u) if u.scheme() == "file" => {
+            let path = u
+                .to_file_path()
+                .map_err(|_| Error::msg(format!("invalid file URL {}", location)))?;
+            Ok((u, std::fs::read_to_string(path)?))
+        }
+        _ => {
+            let path = std::fs::canonicalize(location)?;
+            let data = std::fs::read_to_string(&path)?;
+            let u = Url::from_file_path(&path).map_err(|_| Error::msg(format!("invalid JNLP path {}", location)))?;
+            Ok((u, data))
+        }
+    }
+}
+
+/// The codebase attribute of the <jnlp> element, relative to the JNLP's own URL,
+/// or the directory of the JNLP file if there is no codebase.
+fn get_codebase(jnlp_url: &Url, codebase: Option<&str>) -> Result<Url, Error> {
+    let mut base = match codebase.map(str::trim).filter(|c| !c.is_empty()) {
+        Some(codebase) => jnlp_url.join(codebase)?,
+        None => jnlp_url.join(".")?,
+    };
+    if !matches!(base.scheme(), "http" | "https") {
+        return Err(Error::msg(format!(
+            "the jars of {} must be downloaded from an http(s) codebase, found {}",
+            jnlp_url, base
+        )));
+    }
+    // the codebase is a directory, without the trailing / its last segment would be replaced while resolving
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    Ok(base)
+}
+
+pub(crate) fn normalize_url(u: &str) -> Result<(String, String), Error> {
     let parsed_url = Url::parse(u)?;
     let mut reconstructed_url = String::with_capacity(u.len());
     reconstructed_url.push_str(parsed_url.scheme());
@@ -501,8 +1001,16 @@ fn has_file_changed(jar_file_path: &Path, hash_in_jnlp: Option<&str>) -> Result<
 }
 #[cfg(test)]
 mod tests {
-    use crate::webstart::normalize_url;
+    use crate::webstart::{
+        attribute_matches, attribute_values, collect_declarations, extract_native_libs, get_codebase,
+        get_file_name_from_url, get_j2ses, get_matching_resources, get_node, get_properties, get_resources,
+        get_system_property_args, is_jnlp_location, normalize_url, prepare_native_dir, sanitize_properties,
+        windows_name, Declarations, ResourceKind, NATIVE_DIR,
+    };
+    use std::fs::File;
+    use reqwest::Url;
     use anyhow::Error;
+    use std::collections::BTreeMap;
 
     #[test]
     pub fn test_normalize_url() -> Result<(), Error> {
@@ -522,4 +1030,340 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    pub fn test_system_property_args() {
+        let mut props = BTreeMap::new();
+        props.insert("user.language".to_string(), "en".to_string());
+        props.insert("log4j.configurationFile".to_string(), "/opt/prod/log4j2.xml".to_string());
+        props.insert(" ".to_string(), "ignored".to_string());
+        props.insert("empty".to_string(), "".to_string());
+
+        let args = get_system_property_args(&props);
+        assert_eq!(
+            vec![
+                "-Dempty=",
+                "-Dlog4j.configurationFile=/opt/prod/log4j2.xml",
+                "-Duser.language=en",
+            ],
+            args
+        );
+    }
+
+    #[test]
+    fn test_jnlp_properties() {
+        let data = std::fs::read_to_string("test-resources/jnlp/custom-properties.jnlp").unwrap();
+        let doc = roxmltree::Document::parse(&data).unwrap();
+        let root = doc.root();
+        let props = get_properties(&get_matching_resources(&root, &["Linux"], &["amd64"]));
+        assert_eq!(9, props.len());
+
+        let props: Vec<String> = sanitize_properties(props).into_iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        assert_eq!(
+            vec![
+                "mirth.client.theme=dark",
+                "acme.audit.endpoint=https://audit.example.com/api?a=1&b=2",
+                "sun.java2d.uiScale=2",
+                "jdk.gtk.version=3",
+                "acme.empty=",
+                "acme.linux.only=true",
+            ],
+            props
+        );
+    }
+
+    #[test]
+    fn test_extension_properties_are_sanitized() {
+        let mut declarations = Declarations::default();
+        for fixture in ["custom-properties.jnlp", "extension-properties.jnlp"] {
+            let data = std::fs::read_to_string(format!("test-resources/jnlp/{}", fixture)).unwrap();
+            let doc = roxmltree::Document::parse(&data).unwrap();
+            collect_declarations(&doc.root(), &["Linux"], &["amd64"], &mut declarations);
+        }
+        assert_eq!(2, declarations.j2ses.len());
+
+        let names: Vec<String> = sanitize_properties(declarations.properties).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![
+                "mirth.client.theme",
+                "acme.audit.endpoint",
+                "sun.java2d.uiScale",
+                "jdk.gtk.version",
+                "acme.empty",
+                "acme.linux.only",
+                "acme.extension.mode",
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn test_jnlp_location_and_codebase() {
+        assert!(is_jnlp_location("https://proxy.example.com/oie/webstart.jnlp"));
+        assert!(is_jnlp_location("https://proxy.example.com/launch.JNLP?x=1"));
+        assert!(is_jnlp_location("/home/me/Downloads/engine.jnlp"));
+        assert!(is_jnlp_location("C:\\Users\\me\\engine.jnlp"));
+        assert!(!is_jnlp_location("https://localhost:8443"));
+        assert!(!is_jnlp_location("https://host/jnlp?file=a"));
+
+        let jnlp_url = Url::parse("https://proxy.example.com/oie/webstart.jnlp").unwrap();
+        assert_eq!("https://proxy.example.com/oie/", get_codebase(&jnlp_url, None).unwrap().as_str());
+        assert_eq!(
+            "https://engine.example.com:8443/",
+            get_codebase(&jnlp_url, Some("https://engine.example.com:8443")).unwrap().as_str()
+        );
+        assert_eq!(
+            "https://proxy.example.com/oie/app/",
+            get_codebase(&jnlp_url, Some("app")).unwrap().as_str()
+        );
+
+        // the jars of a local file must come from a server
+        let local = Url::parse("file:///home/me/engine.jnlp").unwrap();
+        assert!(get_codebase(&local, None).is_err());
+        assert_eq!(
+            "https://engine.example.com/",
+            get_codebase(&local, Some("https://engine.example.com")).unwrap().as_str()
+        );
+    }
+
+    /// resolves the hrefs of a fixture as if it was downloaded from jnlp_url
+    fn resolve_fixture(file_name: &str, jnlp_url: &str) -> Vec<String> {
+        let data = std::fs::read_to_string(format!("test-resources/jnlp/{}", file_name)).unwrap();
+        let doc = roxmltree::Document::parse(&data).unwrap();
+        let root = doc.root();
+        let jnlp_url = Url::parse(jnlp_url).unwrap();
+        let codebase = get_codebase(&jnlp_url, get_node(&root, "jnlp").and_then(|n| n.attribute("codebase"))).unwrap();
+        let resources_node = get_node(&root, "resources").unwrap();
+        get_resources(&resources_node, &codebase)
+            .unwrap()
+            .into_iter()
+            .map(|r| match r.kind {
+                ResourceKind::Jar => r.url.to_string(),
+                ResourceKind::NativeLib => format!("native {}", r.url),
+                ResourceKind::Extension => format!("ext {}", r.url),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_hrefs() {
+        assert_eq!(
+            vec![
+                "https://localhost:8443/webstart/client-lib/mirth-client.jar",
+                "https://localhost:8443/webstart/client-lib/mirth-client-core.jar",
+                "https://localhost:8443/webstart/client-lib/commons-lang3-3.9.jar",
+                "ext https://localhost:8443/webstart/extensions/datatype-hl7v2.jnlp",
+                "ext https://localhost:8443/webstart/extensions/dashboardstatus.jnlp",
+            ],
+            resolve_fixture("mirth-3.12-webstart.jnlp", "https://localhost:8443/webstart.jnlp")
+        );
+
+        // the codebase wins over the location the JNLP was downloaded from
+        assert_eq!(
+            vec![
+                "https://engine.example.com:8443/webstart/client-lib/mirth-client.jar",
+                "https://engine.example.com:8443/webstart/client-lib/mirth-client-core.jar",
+                "ext https://engine.example.com:8443/webstart/extensions/datatype-hl7v2.jnlp",
+            ],
+            resolve_fixture("mirth-4.5-webstart.jnlp", "https://10.0.0.5:8443/webstart.jnlp")
+        );
+
+        // no codebase, relative, absolute path and absolute URL hrefs
+        assert_eq!(
+            vec![
+                "https://proxy.example.com/engines/prod/webstart/client-lib/mirth-client.jar",
+                "https://proxy.example.com/shared/client-lib/bcprov-jdk18on.jar",
+                "https://cdn.example.com/oie/4.5.2/jackson-core.jar?sig=abc",
+                "ext https://proxy.example.com/engines/prod/webstart/extensions/datatype-hl7v2.jnlp",
+            ],
+            resolve_fixture("oie-4.5-proxied.jnlp", "https://proxy.example.com/engines/prod/webstart.jnlp")
+        );
+
+        assert_eq!(
+            vec![
+                "https://intranet.example.com/oie/webstart/client-lib/mirth-client.jar",
+                "https://intranet.example.com/oie/webstart/client-lib/mirth-client-core.jar",
+            ],
+            resolve_fixture("relative-codebase.jnlp", "https://intranet.example.com/launch/engine.jnlp")
+        );
+
+        // extensions are resolved against their own codebase or location
+        assert_eq!(
+            vec![
+                "https://localhost:8443/webstart/extensions/libs/datatype-hl7v2/datatype-hl7v2-client.jar",
+                "https://localhost:8443/webstart/extensions/libs/datatype-hl7v2/datatype-hl7v2-shared.jar",
+            ],
+            resolve_fixture("datatype-hl7v2.jnlp", "https://localhost:8443/webstart/extensions/datatype-hl7v2.jnlp")
+        );
+        assert_eq!(
+            vec!["https://proxy.example.com/engines/prod/webstart/extensions/libs/dashboardstatus/dashboardstatus-client.jar"],
+            resolve_fixture(
+                "dashboardstatus-no-codebase.jnlp",
+                "https://proxy.example.com/engines/prod/webstart/extensions/dashboardstatus.jnlp"
+            )
+        );
+    }
+
+    #[test]
+    fn test_file_name_from_url() {
+        let url = Url::parse("https://cdn.example.com/oie/jackson-core.jar?sig=abc").unwrap();
+        assert_eq!("jackson-core.jar", get_file_name_from_url(&url).unwrap());
+        assert!(get_file_name_from_url(&Url::parse("https://cdn.example.com/oie/").unwrap()).is_err());
+    }
+
+    /// the resources of the elements matching the given os and arch
+    fn matching_fixture(os: &[&str], arch: &[&str]) -> Vec<String> {
+        matching_resources("test-resources/jnlp/native-libs.jnlp", os, arch)
+    }
+
+    fn matching_resources(path: &str, os: &[&str], arch: &[&str]) -> Vec<String> {
+        let data = std::fs::read_to_string(path).unwrap();
+        let doc = roxmltree::Document::parse(&data).unwrap();
+        let root = doc.root();
+        let codebase = Url::parse("https://localhost:8443/webstart/").unwrap();
+        let mut resources = Vec::new();
+        for n in get_matching_resources(&root, os, arch) {
+            for r in get_resources(&n, &codebase).unwrap() {
+                let name = get_file_name_from_url(&r.url).unwrap().to_string();
+                resources.push(if r.kind == ResourceKind::NativeLib { format!("native {}", name) } else { name });
+            }
+        }
+        resources
+    }
+
+    #[test]
+    fn test_resources_by_os_and_arch() {
+        assert_eq!(
+            vec!["mirth-client.jar", "native serial-windows-x64.jar", "serial-windows.jar"],
+            matching_fixture(&["Windows 10"], &["amd64", "x86_64", "x64"])
+        );
+        // the values are prefixes of the full os.name, a space within a value is escaped
+        assert_eq!(
+            vec!["mirth-client.jar", "serial-windows.jar", "serial-windows-11.jar"],
+            matching_fixture(&["Windows 11"], &["aarch64", "arm64"])
+        );
+        assert_eq!(
+            vec!["mirth-client.jar", "serial-windows.jar", "serial-windows-11.jar"],
+            matching_fixture(&["Windows Server 2025"], &["aarch64", "arm64"])
+        );
+        assert_eq!(
+            vec!["mirth-client.jar", "native serial-linux-aarch64.jar"],
+            matching_fixture(&["Linux"], &["aarch64", "arm64"])
+        );
+        // the element without an arch applies to all of them
+        assert_eq!(
+            vec!["mirth-client.jar", "native serial-macos.jar"],
+            matching_fixture(&["Mac OS X"], &["aarch64", "arm64"])
+        );
+        assert_eq!(vec!["mirth-client.jar"], matching_fixture(&["SunOS"], &["sparc"]));
+
+        // the j2se elements of the other platforms are ignored
+        let data = std::fs::read_to_string("test-resources/jnlp/native-libs.jnlp").unwrap();
+        let doc = roxmltree::Document::parse(&data).unwrap();
+        let root = doc.root();
+        let j2ses = get_j2ses(&get_matching_resources(&root, &["Linux"], &["amd64"])).unwrap();
+        assert_eq!(1, j2ses.len());
+        assert_eq!("-Xmx512m", j2ses[0].java_vm_args.as_deref().unwrap());
+    }
+
+    #[test]
+    fn test_resources_of_the_selected_j2se() {
+        let path = "test-resources/jnlp/j2se-resources.jnlp";
+        assert_eq!(
+            vec!["mirth-client.jar", "java17-support.jar"],
+            matching_resources(path, &["Linux"], &["amd64"])
+        );
+        // only the first matching j2se is selected, the nested resources of the others are ignored
+        assert_eq!(
+            vec!["mirth-client.jar", "serial-windows.jar", "java17-support.jar"],
+            matching_resources(path, &["Windows 11"], &["amd64"])
+        );
+
+        let data = std::fs::read_to_string(path).unwrap();
+        let doc = roxmltree::Document::parse(&data).unwrap();
+        let matching = get_matching_resources(&doc.root(), &["Linux"], &["amd64"]);
+        assert!(get_properties(&matching).is_empty());
+        assert_eq!(2, get_j2ses(&matching).unwrap().len());
+    }
+
+    #[test]
+    fn test_attribute_values() {
+        assert_eq!(vec!["x86_64", "amd64"], attribute_values(" x86_64  amd64 "));
+        assert_eq!(vec!["Mac OS X", "Linux"], attribute_values("Mac\\ OS\\ X Linux"));
+        assert!(attribute_values("").is_empty());
+        assert!(attribute_matches(None, &["Linux"]));
+        assert!(!attribute_matches(Some("Windows\\ 11"), &["Windows 10"]));
+    }
+
+    #[test]
+    fn test_windows_name() {
+        assert_eq!("Windows 10", windows_name(10, 0, 19045, true));
+        assert_eq!("Windows 11", windows_name(10, 0, 22631, true));
+        assert_eq!("Windows Server 2019", windows_name(10, 0, 17763, false));
+        assert_eq!("Windows Server 2022", windows_name(10, 0, 20348, false));
+        assert_eq!("Windows 8.1", windows_name(6, 3, 9600, true));
+    }
+
+    #[test]
+    fn test_extract_native_libs() {
+        let dir = std::env::temp_dir().join(format!("ballista-test-{}", uuid::Uuid::new_v4()));
+        let native_dir = dir.join(NATIVE_DIR);
+        std::fs::create_dir_all(&native_dir).unwrap();
+        let jar = dir.join("native.jar");
+
+        let mut zw = zip::ZipWriter::new(File::create(&jar).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, data) in [
+            ("libserial.so", "lib"),
+            ("META-INF/MANIFEST.MF", "Manifest-Version: 1.0"),
+            ("sub/libother.so", "nested"),
+            ("../escape.so", "escape"),
+        ] {
+            zw.start_file(name, options).unwrap();
+            std::io::Write::write_all(&mut zw, data.as_bytes()).unwrap();
+        }
+        zw.finish().unwrap();
+
+        extract_native_libs(&jar, &native_dir).unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(&native_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(vec!["libserial.so"], names);
+        assert!(!dir.join("escape.so").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prepare_native_dir() {
+        let dir = std::env::temp_dir().join(format!("ballista-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let jar = dir.join("native.jar");
+        let write_jar = |data: &str| {
+            let mut zw = zip::ZipWriter::new(File::create(&jar).unwrap());
+            zw.start_file("libserial.so", zip::write::SimpleFileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut zw, data.as_bytes()).unwrap();
+            zw.finish().unwrap();
+        };
+
+        assert_eq!(None, prepare_native_dir(&dir, &[]).unwrap());
+
+        write_jar("v1");
+        let first = prepare_native_dir(&dir, std::slice::from_ref(&jar)).unwrap().unwrap();
+        assert!(first.starts_with(dir.join(NATIVE_DIR)));
+        assert_eq!("v1", std::fs::read_to_string(first.join("libserial.so")).unwrap());
+        // an unchanged jar is not extracted again
+        std::fs::write(first.join("marker"), "").unwrap();
+        assert_eq!(first, prepare_native_dir(&dir, std::slice::from_ref(&jar)).unwrap().unwrap());
+        assert!(first.join("marker").exists());
+
+        write_jar("v2");
+        let second = prepare_native_dir(&dir, std::slice::from_ref(&jar)).unwrap().unwrap();
+        assert_ne!(first, second);
+        assert_eq!("v2", std::fs::read_to_string(second.join("libserial.so")).unwrap());
+        assert!(!first.exists());
+        assert_eq!(1, dir.join(NATIVE_DIR).read_dir().unwrap().count());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }